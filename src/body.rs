@@ -0,0 +1,132 @@
+use crate::chunked::ChunkedEncoder;
+
+use bytes::Bytes;
+use std::fmt::{Debug, Formatter};
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// A response body. Files and streams are copied to the socket one buffer at
+/// a time, each write waiting for the client to keep up, so memory use does
+/// not grow with their size.
+pub enum Body {
+    /// held in memory, sent with a Content-Length
+    Bytes(Bytes),
+
+    /// an open file and its size, sent with a Content-Length
+    File(File, u64),
+
+    /// a reader of unknown length, sent chunked
+    Stream(Box<dyn AsyncRead + Send + Unpin>),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Bytes(Bytes::new())
+    }
+
+    /// Opens the file at `path`, failing with `NotFound` for anything but a regular file.
+    pub async fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::open(path).await?;
+
+        let metadata = file.metadata().await?;
+
+        if !metadata.is_file() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "not a regular file"));
+        }
+
+        Ok(Body::File(file, metadata.len()))
+    }
+
+    /// The size in bytes, when it is known before the body is sent.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File(_, size) => Some(*size),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The content of an in-memory body.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Writes the body to `writer`, framing a stream as chunks when `chunked` is set.
+    pub async fn write_to<W: AsyncWrite + Unpin>(self, writer: &mut W, chunked: bool) -> std::io::Result<()> {
+        match self {
+            Body::Bytes(bytes) => writer.write_all(&bytes).await,
+
+            Body::File(file, size) => {
+                let copied = tokio::io::copy(&mut file.take(size), writer).await?;
+
+                // the Content-Length already sent can no longer be honoured
+                if copied < size {
+                    return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "file shrank while it was being sent"));
+                }
+
+                Ok(())
+            }
+
+            Body::Stream(mut reader) if chunked => {
+                let mut encoder = ChunkedEncoder::new(writer);
+
+                encoder.copy_from(&mut reader).await?;
+
+                encoder.finish().await?;
+
+                Ok(())
+            }
+
+            Body::Stream(mut reader) => tokio::io::copy(&mut reader, writer).await.map(|_| ()),
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Body::File(_, size) => f.debug_tuple("File").field(size).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes.into())
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::Bytes(Bytes::from_static(text.as_bytes()))
+    }
+}
@@ -0,0 +1,108 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Frames everything written through it as the chunks of a
+/// `Transfer-Encoding: chunked` body. `finish` must be called to send the
+/// terminating zero-length chunk.
+pub struct ChunkedEncoder<W: AsyncWrite + Unpin> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin> ChunkedEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub async fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.write_all(format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+
+        self.inner.write_all(chunk).await?;
+
+        self.inner.write_all(b"\r\n").await
+    }
+
+    /// Copies `reader` to the underlying writer, one chunk per read.
+    pub async fn copy_from<R: AsyncRead + Unpin + ?Sized>(&mut self, reader: &mut R) -> std::io::Result<u64> {
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+
+        let mut copied = 0;
+
+        loop {
+            let bytes_read = reader.read(&mut buffer).await?;
+
+            if bytes_read == 0 {
+                return Ok(copied);
+            }
+
+            self.write_chunk(&buffer[..bytes_read]).await?;
+
+            copied += bytes_read as u64;
+        }
+    }
+
+    pub async fn finish(mut self) -> std::io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n").await?;
+
+        self.inner.flush().await?;
+
+        Ok(self.inner)
+    }
+}
+
+/// Parses a chunk-size line (without its CRLF), ignoring any chunk extensions.
+pub fn parse_chunk_size(line: &[u8]) -> Option<usize> {
+    let line = std::str::from_utf8(line).ok()?;
+
+    let size = match line.split_once(';') {
+        None => line,
+        Some((size, _extensions)) => size,
+    };
+
+    let size = size.trim();
+
+    if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    usize::from_str_radix(size, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_chunk_sizes() {
+        assert_eq!(parse_chunk_size(b"0"), Some(0));
+
+        assert_eq!(parse_chunk_size(b"1a"), Some(26));
+
+        assert_eq!(parse_chunk_size(b"FF"), Some(255));
+
+        assert_eq!(parse_chunk_size(b"10;name=value"), Some(16));
+
+        assert_eq!(parse_chunk_size(b"FFFFFFFFFFFFFFFF"), Some(usize::MAX));
+    }
+
+    #[test]
+    fn rejects_malformed_chunk_sizes() {
+        assert_eq!(parse_chunk_size(b""), None);
+
+        assert_eq!(parse_chunk_size(b";ext"), None);
+
+        assert_eq!(parse_chunk_size(b"+1"), None);
+
+        assert_eq!(parse_chunk_size(b"-1"), None);
+
+        assert_eq!(parse_chunk_size(b"0x10"), None);
+
+        assert_eq!(parse_chunk_size(b"1 2"), None);
+
+        assert_eq!(parse_chunk_size(b"10000000000000000"), None);
+    }
+}
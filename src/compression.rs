@@ -0,0 +1,350 @@
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{Read, Write};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("unsupported Content-Encoding: {0}")]
+    Unsupported(String),
+
+    #[error("decoded body exceeds the limit of {0} bytes")]
+    TooLarge(usize),
+
+    #[error("malformed {0} body: {1}")]
+    Invalid(&'static str, std::io::Error),
+}
+
+/// A content-coding response bodies can be compressed with. Brotli and zstd
+/// are only available with the cargo features of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[cfg(feature = "zstd")]
+    Zstd,
+
+    #[cfg(feature = "brotli")]
+    Brotli,
+
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Every supported encoding, the one the server prefers on a tie first.
+    pub fn supported() -> Vec<Encoding> {
+        vec![
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd,
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli,
+            Encoding::Gzip,
+            Encoding::Deflate,
+        ]
+    }
+
+    pub fn from_token(token: &str) -> Option<Encoding> {
+        match token.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Encoding::Zstd),
+
+            #[cfg(feature = "brotli")]
+            "br" => Some(Encoding::Brotli),
+
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// The content-coding name used in Accept-Encoding and Content-Encoding.
+    pub fn token(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
+
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => zstd::encode_all(content, 0),
+
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+
+                encoder.write_all(content)?;
+
+                Ok(encoder.into_inner())
+            }
+
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+                encoder.write_all(content)?;
+
+                encoder.finish()
+            }
+
+            // the "deflate" content-coding is the zlib format (RFC 9110, section 8.4.1.2)
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+
+                encoder.write_all(content)?;
+
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Decodes `content`, giving up as soon as the output grows past `max_size`
+    /// so a small compressed body cannot expand without bounds.
+    pub fn decode(&self, content: &[u8], max_size: usize) -> Result<Vec<u8>, DecodeError> {
+        let invalid = |err| DecodeError::Invalid(self.token(), err);
+
+        let decoder: Box<dyn Read + '_> = match self {
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Box::new(zstd::Decoder::new(content).map_err(invalid)?),
+
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(content, 4096)),
+
+            Encoding::Gzip => Box::new(MultiGzDecoder::new(content)),
+            Encoding::Deflate => Box::new(ZlibDecoder::new(content)),
+        };
+
+        let mut decoded = Vec::new();
+
+        decoder.take(max_size as u64 + 1).read_to_end(&mut decoded).map_err(invalid)?;
+
+        if decoded.len() > max_size {
+            return Err(DecodeError::TooLarge(max_size));
+        }
+
+        Ok(decoded)
+    }
+}
+
+/// Undoes the content-codings of a request body, listed in the order they
+/// were applied (RFC 9110, section 8.4). Every coding is checked before
+/// anything is decoded.
+pub fn decode(codings: &[&str], body: &[u8], max_size: usize) -> Result<Vec<u8>, DecodeError> {
+    let encodings = codings
+        .iter()
+        .filter(|coding| !coding.eq_ignore_ascii_case("identity"))
+        .map(|coding| Encoding::from_token(coding).ok_or_else(|| DecodeError::Unsupported(coding.to_string())))
+        .collect::<Result<Vec<Encoding>, DecodeError>>()?;
+
+    let mut body = body.to_vec();
+
+    for encoding in encodings.iter().rev() {
+        body = encoding.decode(&body, max_size)?;
+    }
+
+    Ok(body)
+}
+
+/// Picks the supported encoding with the highest weight among the elements
+/// of Accept-Encoding (RFC 9110, section 12.5.3), falling back to the weight
+/// of `*` for codings that are not listed. Returns `None` when the body
+/// should be sent as is.
+pub fn negotiate(accepted: &[&str]) -> Option<Encoding> {
+    let weights = accepted.iter().filter_map(|element| parse_element(element)).collect::<Vec<(String, f32)>>();
+
+    let weight_of = |token: &str| {
+        let weight = weights.iter().find(|(coding, _)| coding == token);
+
+        weight.or_else(|| weights.iter().find(|(coding, _)| coding == "*")).map_or(0.0, |(_, weight)| *weight)
+    };
+
+    let mut best = None;
+
+    let mut best_weight = 0.0;
+
+    for encoding in Encoding::supported() {
+        let weight = weight_of(encoding.token());
+
+        if weight > best_weight {
+            best = Some(encoding);
+
+            best_weight = weight;
+        }
+    }
+
+    best
+}
+
+/// Splits an element such as `gzip;q=0.8` into its coding and weight.
+/// Elements with a malformed weight are ignored.
+fn parse_element(element: &str) -> Option<(String, f32)> {
+    let mut parts = element.split(';');
+
+    let coding = match parts.next()?.trim().to_ascii_lowercase().as_str() {
+        "x-gzip" => "gzip".to_string(),
+        coding => coding.to_string(),
+    };
+
+    let mut weight = 1.0;
+
+    for parameter in parts {
+        let (name, value) = parameter.split_once('=')?;
+
+        if !name.trim().eq_ignore_ascii_case("q") {
+            continue;
+        }
+
+        weight = value.trim().parse::<f32>().ok().filter(|weight| (0.0..=1.0).contains(weight))?;
+    }
+
+    Some((coding, weight))
+}
+
+/// Whether a body of `content_type` is worth compressing; images, audio,
+/// video and archives are already compressed.
+pub fn is_compressible(content_type: Option<&str>) -> bool {
+    let media_type = content_type.unwrap_or_default().split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    match media_type.as_str() {
+        "image/svg+xml" => true,
+
+        media_type if media_type.starts_with("image/") || media_type.starts_with("audio/") || media_type.starts_with("video/") => false,
+
+        "application/zip"
+        | "application/gzip"
+        | "application/x-gzip"
+        | "application/zstd"
+        | "application/x-bzip2"
+        | "application/x-xz"
+        | "application/x-7z-compressed"
+        | "application/x-rar-compressed"
+        | "font/woff"
+        | "font/woff2" => false,
+
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_codings_and_weights() {
+        assert_eq!(parse_element("gzip"), Some(("gzip".to_string(), 1.0)));
+
+        assert_eq!(parse_element(" GZIP ; Q=0.5 "), Some(("gzip".to_string(), 0.5)));
+
+        assert_eq!(parse_element("x-gzip;q=0"), Some(("gzip".to_string(), 0.0)));
+
+        assert_eq!(parse_element("deflate;level=1;q=0.3"), Some(("deflate".to_string(), 0.3)));
+    }
+
+    #[test]
+    fn ignores_malformed_weights() {
+        assert_eq!(parse_element("gzip;q=2"), None);
+
+        assert_eq!(parse_element("gzip;q=-1"), None);
+
+        assert_eq!(parse_element("gzip;q=high"), None);
+
+        assert_eq!(parse_element("gzip;q"), None);
+    }
+
+    #[test]
+    fn picks_the_highest_weight() {
+        assert_eq!(negotiate(&["gzip;q=0.5", "deflate"]), Some(Encoding::Deflate));
+
+        assert_eq!(negotiate(&["deflate;q=0.1", "gzip;q=0.9"]), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn breaks_ties_in_server_order() {
+        assert_eq!(negotiate(&["deflate", "gzip"]), Some(Encoding::Gzip));
+
+        assert_eq!(negotiate(&["deflate;q=0.5", "gzip;q=0.5"]), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn falls_back_to_the_wildcard_weight() {
+        assert_eq!(negotiate(&["*"]), Some(Encoding::supported()[0]));
+
+        let negotiated = negotiate(&["gzip;q=0", "*;q=0.5"]);
+
+        assert!(negotiated.is_some_and(|encoding| encoding != Encoding::Gzip));
+
+        assert_eq!(negotiate(&["deflate", "*;q=0"]), Some(Encoding::Deflate));
+    }
+
+    #[test]
+    fn sends_the_body_as_is_when_nothing_is_acceptable() {
+        assert_eq!(negotiate(&[]), None);
+
+        assert_eq!(negotiate(&["identity"]), None);
+
+        assert_eq!(negotiate(&["gzip;q=0", "deflate;q=0"]), None);
+
+        assert_eq!(negotiate(&["*;q=0"]), None);
+
+        assert_eq!(negotiate(&["gzip;q=2"]), None);
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        for encoding in Encoding::supported() {
+            let encoded = encoding.encode(b"hello hello hello").unwrap();
+
+            assert_eq!(encoding.decode(&encoded, 1024).unwrap(), b"hello hello hello", "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn undoes_stacked_codings_in_reverse_order() {
+        let encoded = Encoding::Gzip.encode(&Encoding::Deflate.encode(b"stacked").unwrap()).unwrap();
+
+        assert_eq!(decode(&["deflate", "gzip"], &encoded, 1024).unwrap(), b"stacked");
+
+        assert!(matches!(decode(&["gzip", "deflate"], &encoded, 1024), Err(DecodeError::Invalid("deflate", _))));
+    }
+
+    #[test]
+    fn skips_identity() {
+        assert_eq!(decode(&["identity"], b"plain", 1024).unwrap(), b"plain");
+
+        let encoded = Encoding::Gzip.encode(b"zipped").unwrap();
+
+        assert_eq!(decode(&["Identity", "gzip", "identity"], &encoded, 1024).unwrap(), b"zipped");
+    }
+
+    #[test]
+    fn rejects_unknown_codings_before_decoding() {
+        let encoded = Encoding::Gzip.encode(b"zipped").unwrap();
+
+        assert!(matches!(decode(&["gzip", "compress"], &encoded, 1024), Err(DecodeError::Unsupported(coding)) if coding == "compress"));
+    }
+
+    #[test]
+    fn stops_decoding_past_the_size_limit() {
+        // a few kilobytes of gzip that expand to 10 MiB
+        let bomb = Encoding::Gzip.encode(&vec![0; 10 * 1024 * 1024]).unwrap();
+
+        assert!(bomb.len() < 64 * 1024);
+
+        assert!(matches!(decode(&["gzip"], &bomb, 1024 * 1024), Err(DecodeError::TooLarge(limit)) if limit == 1024 * 1024));
+
+        assert!(matches!(Encoding::Deflate.decode(&Encoding::Deflate.encode(&[0; 1025]).unwrap(), 1024), Err(DecodeError::TooLarge(1024))));
+
+        assert_eq!(Encoding::Deflate.decode(&Encoding::Deflate.encode(&[0; 1024]).unwrap(), 1024).unwrap().len(), 1024);
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        assert!(matches!(decode(&["gzip"], b"not gzip", 1024), Err(DecodeError::Invalid("gzip", _))));
+    }
+}
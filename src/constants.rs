@@ -1 +1,11 @@
-pub const CRLF: &str = "\r\n";
\ No newline at end of file
+use std::time::Duration;
+
+pub const CRLF: &str = "\r\n";
+
+pub const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+pub const MAX_FIELD_SIZE: usize = 4 * 1024;
+
+/// How long to wait after a failed accept, so running out of file
+/// descriptors does not turn the accept loop into a busy loop.
+pub const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
@@ -0,0 +1,127 @@
+use codecrafters_http_server::headers::LOCATION;
+use codecrafters_http_server::response::{ContentType, HttpCode};
+use codecrafters_http_server::router::Router;
+
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// Routes for reading and writing files in the `--directory` folder, meant to be nested under `/files`.
+pub fn router() -> Router {
+    let router = Router::new();
+
+    router
+        .get("/:filename", |req, res| Box::pin(async move {
+            if let Some(filename) = req.params.get("filename") {
+                let Some(file_path) = file_path(&req.server_options.files_directory, filename) else {
+                    res.set_body_string(String::from("{ \"message\": \"Invalid filename\" }"), None);
+                    res.status = HttpCode::BadRequest;
+
+                    return Ok(());
+                };
+
+                match res.set_body_file(file_path, Some(ContentType::OctetStream)).await {
+                    Ok(_) => {
+                        res.status = HttpCode::Ok;
+                    }
+
+                    Err(err) => {
+                        match err.kind() {
+                            ErrorKind::NotFound => {
+                                res.status = HttpCode::NotFound;
+
+                                let message = format!("File not found: {}", filename);
+
+                                let content = "{ \"message\": \"".to_string() + message.as_str() + "\" }";
+
+                                res.set_json_body(content);
+                            }
+                            _ => {
+                                eprintln!("{:?}", err);
+
+                                res.status = HttpCode::InternalServerError;
+
+                                let content = "{ \"message\": \"Internal Server Error\" }".to_string();
+
+                                res.set_json_body(content);
+                            }
+                        }
+                    }
+                };
+            } else {
+                res.set_body_string(String::from("{ \"message\": \"Param filename is required\" }"), None);
+                res.status = HttpCode::BadRequest;
+            }
+
+            Ok(())
+        }))
+        .name("file")
+
+        .post("/:filename", |req, res| Box::pin(async move {
+            if let Some(filename) = req.params.get("filename") {
+                let Some(file_path) = file_path(&req.server_options.files_directory, filename) else {
+                    res.set_body_string(String::from("{ \"message\": \"Invalid filename\" }"), None);
+                    res.status = HttpCode::BadRequest;
+
+                    return Ok(());
+                };
+
+                let result = OpenOptions::new().write(true).create(true).truncate(true).open(file_path).await;
+
+                match result {
+                    Ok(mut file) => {
+                        let written = match file.write_all(&req.body).await {
+                            Ok(_) => file.flush().await,
+                            Err(err) => Err(err),
+                        };
+
+                        match written {
+                            Ok(_) => {
+                                res.status = HttpCode::Created;
+
+                                res.set_header(LOCATION, req.url_for("file", &[("filename", filename)], &[])?);
+                            }
+                            Err(err) => {
+                                eprintln!("{:?}", err);
+
+                                res.status = HttpCode::InternalServerError;
+
+                                let content = "{ \"message\": \"Internal Server Error\" }".to_string();
+
+                                res.set_json_body(content);
+                            }
+                        }
+                    }
+
+                    Err(err) => {
+                        eprintln!("{:?}", err);
+
+                        res.status = HttpCode::InternalServerError;
+
+                        let content = "{ \"message\": \"Internal Server Error\" }".to_string();
+
+                        res.set_json_body(content);
+                    }
+                };
+            } else {
+                res.set_body_string(String::from("{ \"message\": \"Param filename is required\" }"), None);
+                res.status = HttpCode::BadRequest;
+            }
+
+            Ok(())
+        }));
+
+    router
+}
+
+/// Joins `filename` to `directory`, unless it is anything but a plain file name;
+/// params are percent-decoded, so `..%2Fsecret` would otherwise escape the directory.
+fn file_path(directory: &str, filename: &str) -> Option<PathBuf> {
+    let mut components = Path::new(filename).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if name == filename => Some(PathBuf::from(directory).join(name)),
+        _ => None,
+    }
+}
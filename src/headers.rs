@@ -0,0 +1,313 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+
+/// A header field name. Names compare case-insensitively and keep the
+/// spelling they were created with for serialization.
+#[derive(Debug, Clone)]
+pub struct HeaderName(Cow<'static, str>);
+
+impl HeaderName {
+    pub const fn from_static(name: &'static str) -> Self {
+        Self(Cow::Borrowed(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for HeaderName {}
+
+impl AsRef<str> for HeaderName {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for HeaderName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<&str> for HeaderName {
+    fn from(name: &str) -> Self {
+        Self(Cow::Owned(name.to_string()))
+    }
+}
+
+impl From<String> for HeaderName {
+    fn from(name: String) -> Self {
+        Self(Cow::Owned(name))
+    }
+}
+
+pub const ACCEPT: HeaderName = HeaderName::from_static("Accept");
+pub const ACCEPT_ENCODING: HeaderName = HeaderName::from_static("Accept-Encoding");
+pub const ALLOW: HeaderName = HeaderName::from_static("Allow");
+pub const CONNECTION: HeaderName = HeaderName::from_static("Connection");
+pub const CONTENT_ENCODING: HeaderName = HeaderName::from_static("Content-Encoding");
+pub const CONTENT_LENGTH: HeaderName = HeaderName::from_static("Content-Length");
+pub const CONTENT_TYPE: HeaderName = HeaderName::from_static("Content-Type");
+pub const COOKIE: HeaderName = HeaderName::from_static("Cookie");
+pub const DATE: HeaderName = HeaderName::from_static("Date");
+pub const HOST: HeaderName = HeaderName::from_static("Host");
+pub const KEEP_ALIVE: HeaderName = HeaderName::from_static("Keep-Alive");
+pub const LOCATION: HeaderName = HeaderName::from_static("Location");
+pub const SERVER: HeaderName = HeaderName::from_static("Server");
+pub const SET_COOKIE: HeaderName = HeaderName::from_static("Set-Cookie");
+pub const TRANSFER_ENCODING: HeaderName = HeaderName::from_static("Transfer-Encoding");
+pub const USER_AGENT: HeaderName = HeaderName::from_static("User-Agent");
+pub const VARY: HeaderName = HeaderName::from_static("Vary");
+
+/// Header fields in the order they were added. Lookups ignore the case of the
+/// name and a name can hold several values, e.g. `Set-Cookie`.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(HeaderName, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&str> {
+        let name = name.as_ref();
+
+        self.entries.iter().find(|(key, _)| key.as_str().eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of `name`, in the order they were added.
+    pub fn get_all(&self, name: impl AsRef<str>) -> Vec<&str> {
+        let name = name.as_ref();
+
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.as_str().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// The elements of a comma-separated list header, across all of its field lines.
+    pub fn get_list(&self, name: impl AsRef<str>) -> Vec<&str> {
+        self.get_all(name)
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| element.trim())
+            .filter(|element| !element.is_empty())
+            .collect()
+    }
+
+    pub fn contains(&self, name: impl AsRef<str>) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to `value`, replacing any values it had. The field keeps
+    /// the position of its first occurrence.
+    pub fn insert(&mut self, name: impl Into<HeaderName>, value: impl Into<String>) {
+        let name = name.into();
+
+        let mut value = Some(value.into());
+
+        // the first occurrence takes the new value, the others are dropped
+        self.entries.retain_mut(|(key, existing)| {
+            if *key != name {
+                return true;
+            }
+
+            match value.take() {
+                Some(value) => {
+                    *existing = value;
+
+                    true
+                }
+
+                None => false,
+            }
+        });
+
+        if let Some(value) = value {
+            self.entries.push((name, value));
+        }
+    }
+
+    /// Adds another value for `name`, keeping the existing ones.
+    pub fn append(&mut self, name: impl Into<HeaderName>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every value of `name` and returns the first one.
+    pub fn remove(&mut self, name: impl AsRef<str>) -> Option<String> {
+        let name = name.as_ref();
+
+        let mut removed = None;
+
+        self.entries.retain_mut(|(key, value)| {
+            if !key.as_str().eq_ignore_ascii_case(name) {
+                return true;
+            }
+
+            if removed.is_none() {
+                removed = Some(std::mem::take(value));
+            }
+
+            false
+        });
+
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<N: Into<HeaderName>, V: Into<String>> Extend<(N, V)> for HeaderMap {
+    fn extend<T: IntoIterator<Item = (N, V)>>(&mut self, fields: T) {
+        for (name, value) in fields {
+            self.append(name, value);
+        }
+    }
+}
+
+/// Whether `value` is an RFC 9110 token, the syntax of methods and field names.
+pub fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Splits a field line into its name and value (RFC 9112, section 5). The
+/// value loses its surrounding whitespace and may itself contain colons; a
+/// name that is not a token, including one followed by whitespace, is rejected.
+pub fn parse_field_line(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+
+    if !is_token(name) {
+        return None;
+    }
+
+    Some((name, value.trim_matches(|c| c == ' ' || c == '\t')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(fields: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        headers.extend(fields.iter().copied());
+
+        headers
+    }
+
+    #[test]
+    fn looks_names_up_case_insensitively() {
+        let headers = map(&[("Content-Type", "text/plain"), ("x-tag", "a"), ("X-TAG", "b")]);
+
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+
+        assert_eq!(headers.get(CONTENT_TYPE), Some("text/plain"));
+
+        assert_eq!(headers.get("X-Tag"), Some("a"));
+
+        assert_eq!(headers.get_all("x-Tag"), ["a", "b"]);
+
+        assert!(headers.contains("CONTENT-TYPE"));
+
+        assert_eq!(headers.get("Accept"), None);
+    }
+
+    #[test]
+    fn insert_keeps_the_first_position_and_drops_duplicates() {
+        let mut headers = map(&[("A", "1"), ("Set-Cookie", "x=1"), ("B", "2"), ("set-cookie", "y=2")]);
+
+        headers.insert("SET-COOKIE", "z=3");
+
+        assert_eq!(headers.iter().collect::<Vec<(&str, &str)>>(), [("A", "1"), ("Set-Cookie", "z=3"), ("B", "2")]);
+
+        headers.insert("C", "3");
+
+        assert_eq!(headers.iter().last(), Some(("C", "3")));
+    }
+
+    #[test]
+    fn append_keeps_existing_values() {
+        let mut headers = map(&[("Vary", "Accept")]);
+
+        headers.append("vary", "Accept-Encoding");
+
+        assert_eq!(headers.get_all(VARY), ["Accept", "Accept-Encoding"]);
+
+        assert_eq!(headers.len(), 2);
+    }
+
+    #[test]
+    fn remove_drops_every_value() {
+        let mut headers = map(&[("X-Tag", "a"), ("Host", "example.com"), ("x-tag", "b")]);
+
+        assert_eq!(headers.remove("X-TAG").as_deref(), Some("a"));
+
+        assert_eq!(headers.iter().collect::<Vec<(&str, &str)>>(), [("Host", "example.com")]);
+
+        assert_eq!(headers.remove("X-Tag"), None);
+    }
+
+    #[test]
+    fn get_list_splits_every_value_on_commas() {
+        let headers = map(&[("Accept-Encoding", "gzip, br;q=0.5"), ("accept-encoding", " , deflate ,")]);
+
+        assert_eq!(headers.get_list(ACCEPT_ENCODING), ["gzip", "br;q=0.5", "deflate"]);
+
+        assert!(headers.get_list("Connection").is_empty());
+    }
+
+    #[test]
+    fn splits_name_and_value() {
+        assert_eq!(parse_field_line("Host: example.com"), Some(("Host", "example.com")));
+
+        assert_eq!(parse_field_line("X-Empty:"), Some(("X-Empty", "")));
+
+        assert_eq!(parse_field_line("X-Tight:value"), Some(("X-Tight", "value")));
+    }
+
+    #[test]
+    fn trims_spaces_and_tabs_around_the_value() {
+        assert_eq!(parse_field_line("Accept: \t text/html \t"), Some(("Accept", "text/html")));
+
+        assert_eq!(parse_field_line("X-Inner: a  b"), Some(("X-Inner", "a  b")));
+    }
+
+    #[test]
+    fn keeps_colons_in_the_value() {
+        assert_eq!(parse_field_line("Host: localhost:4221"), Some(("Host", "localhost:4221")));
+    }
+
+    #[test]
+    fn rejects_lines_without_a_token_name() {
+        assert_eq!(parse_field_line("no colon"), None);
+
+        assert_eq!(parse_field_line(": value"), None);
+
+        assert_eq!(parse_field_line("Host : example.com"), None);
+
+        assert_eq!(parse_field_line(" Host: example.com"), None);
+
+        assert_eq!(parse_field_line("Bad(Name): value"), None);
+    }
+}
@@ -1,134 +1,411 @@
+use crate::compression::{DecodeError, Encoding};
+use crate::constants::ACCEPT_ERROR_BACKOFF;
+use crate::headers;
 use crate::options::Options;
-use crate::request::Request;
+use crate::request::{HTTPMethod, ParseError, Request};
+use crate::request_reader::{ReadError, RequestReader};
 use crate::response::{HttpCode, Response};
-use crate::router::Router;
-
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use crate::middleware::{self, Next};
+use crate::router::{ParamError, RequestHandler, RouteError, RouteMatch, Router};
+use crate::shutdown::{wait_for_signal, ShutdownHandle};
 
 use clap::Parser;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("invalid route: {0}")]
+    Route(#[from] RouteError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
 
 pub struct HttpServer {
     options: Options,
     pub router: Router,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl Default for HttpServer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HttpServer {
     pub fn new() -> HttpServer {
         let options = Options::parse();
 
+        let (shutdown, _) = watch::channel(false);
+
+        let router = Router::new();
+
+        router.middleware(middleware::compression);
+
         Self {
             options,
-            router: Router::new(),
+            router,
+            shutdown: Arc::new(shutdown),
         }
     }
 
-    pub fn listen(self) {
-        let listener = TcpListener::bind(format!("{}:{}", &self.options.host, &self.options.port)).unwrap();
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(Arc::clone(&self.shutdown))
+    }
+
+    /// Serves connections until SIGINT/SIGTERM is received or a `ShutdownHandle`
+    /// is triggered, then waits up to the grace period for open connections to finish.
+    /// Fails before binding if any route could not be registered.
+    pub async fn listen(self) -> Result<(), ServerError> {
+        self.router.validate()?;
+
+        if self.options.print_routes {
+            for route in self.router.routes() {
+                println!("{:<8} {:<32} {}", route.method.to_string(), route.pattern, route.name.unwrap_or_default());
+            }
+
+            return Ok(());
+        }
+
+        let listener = TcpListener::bind(format!("{}:{}", &self.options.host, &self.options.port)).await?;
 
         println!("Server is listening on {}:{}", self.options.host, self.options.port);
 
-        // let pool = ThreadPool::new(500);
+        let mut connections = JoinSet::new();
+
+        let mut shutdown = self.shutdown.subscribe();
+
+        let signal = wait_for_signal();
+
+        tokio::pin!(signal);
 
         loop {
-            for stream in listener.incoming() {
-                let mut stream = stream.unwrap();
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let mut stream = match accepted {
+                        Ok((stream, _)) => stream,
+
+                        Err(err) => {
+                            eprintln!("Could not accept connection: {}", err);
 
+                            tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
 
-                // pool.execute(move || {
-                //     HttpServer::handle_connection(&mut stream)
-                // })
+                            continue;
+                        }
+                    };
+
+                    let router = self.router.clone();
+                    let options = self.options.clone();
+                    let shutdown = self.shutdown.subscribe();
+
+                    connections.spawn(async move { handle_connection(&mut stream, router, options, shutdown).await; });
+                }
 
-                let router = self.router.clone();
-                let options = self.options.clone();
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
 
-                tokio::spawn(async move { handle_connection(&mut stream, router, options).await; });
+                _ = &mut signal => {
+                    println!("Received shutdown signal");
+
+                    self.shutdown.send_replace(true);
+
+                    break;
+                }
+
+                _ = shutdown.wait_for(|stopped| *stopped) => break,
             }
         }
+
+        drop(listener);
+
+        println!("Waiting up to {}s for {} connection(s) to finish", self.options.shutdown_grace_period, connections.len());
+
+        let grace_period = Duration::from_secs(self.options.shutdown_grace_period);
+
+        let drained = tokio::time::timeout(grace_period, async {
+            while connections.join_next().await.is_some() {}
+        }).await;
+
+        if drained.is_err() {
+            eprintln!("Grace period expired, aborting {} connection(s)", connections.len());
+
+            connections.shutdown().await;
+        }
+
+        println!("Server stopped");
+
+        Ok(())
     }
 }
 
-async fn handle_connection(_stream: &mut TcpStream, router: Router, options: Options) {
-    println!("accepted new connection from {}", _stream.peer_addr().unwrap());
+async fn handle_connection(stream: &mut TcpStream, router: Router, options: Options, mut shutdown: watch::Receiver<bool>) {
+    let peer_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(err) => {
+            eprintln!("Could not get peer address: {}", err);
 
-    let mut buffer = vec![0u8; 1024];
+            return;
+        }
+    };
 
-    let bits_read = _stream.read(&mut buffer).expect("Could not read client request");
+    println!("accepted new connection from {}", peer_addr);
 
-    let request_str = String::from_utf8_lossy(&&buffer[0..bits_read]).to_string();
+    let keep_alive_timeout = Duration::from_secs(options.keep_alive_timeout);
 
-    let is_http = request_str.contains("HTTP/1.");
+    let mut requests_served = 0;
 
-    if is_http {
-        let mut request = Request::new(request_str, options);
-        let mut response = Response::from(&request);
+    let mut reader = RequestReader::new(keep_alive_timeout, options.max_header_size, options.max_body_size);
 
-        let (handler, params) = router.get_handler(&request.method, request.url.as_str());
+    loop {
+        // wait for the next request without holding up a shutdown, unless part
+        // of it has already arrived
+        if !reader.has_buffered_data() {
+            tokio::select! {
+                readable = stream.readable() => {
+                    if let Err(err) = readable {
+                        eprintln!("Could not read client request: {}", err);
 
-        match handler {
-            None => {
-                response.status = HttpCode::NotFound;
-            }
+                        break;
+                    }
+                }
 
-            Some(handler) => {
-                for (key, position) in params {
-                    let mut param = String::new();
+                _ = tokio::time::sleep(keep_alive_timeout) => {
+                    println!("closing idle connection from {}", peer_addr);
 
-                    for (i, char) in request.url.chars().enumerate() {
-                        if i as i32 >= position {
-                            if char == '/' { break; }
+                    break;
+                }
 
-                            param.push(char);
-                        }
-                    }
+                _ = shutdown.wait_for(|stopped| *stopped) => {
+                    println!("closing idle connection from {} for shutdown", peer_addr);
 
-                    request.params.insert(key.clone(), param);
+                    break;
                 }
+            }
+        }
 
-                let result = handler(&request, &mut response);
+        let raw_request = match reader.read_request(stream).await {
+            Ok(Some(raw_request)) => raw_request,
 
-                match result {
-                    Ok(_) => {}
+            Ok(None) => break,
 
-                    Err(err) => {
-                        eprintln!("{}", err);
+            Err(ReadError::Io(err)) => {
+                match err.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                        println!("closing idle connection from {}", peer_addr);
+                    }
 
-                        response.status = HttpCode::InternalServerError;
+                    _ => {
+                        eprintln!("Could not read client request: {}", err);
                     }
                 }
+
+                break;
+            }
+
+            Err(ReadError::UnexpectedEof) => {
+                println!("connection from {} closed mid-request", peer_addr);
+
+                break;
+            }
+
+            Err(err) => {
+                eprintln!("Rejecting request from {}: {}", peer_addr, err);
+
+                let status = match err {
+                    ReadError::HeadersTooLarge(_) => HttpCode::RequestHeaderFieldsTooLarge,
+                    ReadError::BodyTooLarge(_) => HttpCode::ContentTooLarge,
+                    ReadError::UnsupportedTransferEncoding(_) => HttpCode::NotImplemented,
+                    _ => HttpCode::BadRequest,
+                };
+
+                write_error(stream, status, &options).await;
+
+                break;
             }
         };
 
-        if !response.body.is_empty() {
-            if let Some(compression) = request.headers.get("Accept-Encoding") {
-                let accepted_compressions = compression.split(',').map(|s| s.trim()).collect::<Vec<&str>>();
+        let mut request = match Request::parse(&raw_request.head, options.clone()) {
+            Ok(request) => request,
 
-                if accepted_compressions.contains(&"gzip") {
-                    let body = encode_gzip(&response.body);
+            Err(err) => {
+                eprintln!("Rejecting request from {}: {}", peer_addr, err);
 
-                    response.set_body(body, None);
+                let status = match err {
+                    ParseError::UnknownMethod(_) => HttpCode::NotImplemented,
+                    ParseError::UnsupportedVersion(_) => HttpCode::HttpVersionNotSupported,
+                    ParseError::FieldTooLarge(_) => HttpCode::RequestHeaderFieldsTooLarge,
+                    ParseError::BadRequestLine(_) | ParseError::BadHeader(_) | ParseError::BadPath(_) => HttpCode::BadRequest,
+                };
 
-                    response.set_header("Content-Encoding".to_string(), "gzip".to_string());
-                }
+                write_error(stream, status, &options).await;
+
+                break;
             }
+        };
+
+        request.set_body(raw_request.body);
+
+        request.trailers.extend(raw_request.trailers);
+
+        let mut response = handle_request(&mut request, &router).await;
+
+        requests_served += 1;
+
+        let limit_reached = options.max_requests_per_connection != 0 && requests_served >= options.max_requests_per_connection;
+
+        let shutting_down = *shutdown.borrow();
+
+        let mut keep_alive = request.is_keep_alive() && !limit_reached && !shutting_down;
+
+        // HTTP/1.0 clients do not understand chunked bodies, so the end of a
+        // streamed body is signalled by closing the connection instead. A HEAD
+        // response has no body to frame and keeps the headers the handler set.
+        if request.method != HTTPMethod::HEAD && !response.set_framing(request.protocol_version != "1.0") {
+            keep_alive = false;
+        }
+
+        if keep_alive {
+            response.set_header(headers::CONNECTION, "keep-alive");
+
+            response.set_header(headers::KEEP_ALIVE, format!("timeout={}", options.keep_alive_timeout));
+        } else {
+            response.set_header(headers::CONNECTION, "close");
+        }
+
+        let written = match request.method {
+            HTTPMethod::HEAD => response.write_head_to(stream).await,
+            _ => response.write_to(stream, None).await,
+        };
+
+        if let Err(err) = written {
+            eprintln!("Failed to send Response to client: {}", err);
+
+            break;
+        }
+
+        if !keep_alive {
+            break;
+        }
+    }
+}
+
+async fn write_error(stream: &mut TcpStream, status: HttpCode, options: &Options) {
+    let mut response = Response::new("HTTP".to_string(), "1.1".to_string());
+
+    response.server = options.server();
+
+    response.status = status;
+
+    response.set_header(headers::CONNECTION, "close");
+
+    vary_on_accept_encoding(&mut response);
+
+    if let Err(err) = response.write_to(stream, None).await {
+        eprintln!("Failed to send Response to client: {}", err);
+    }
+}
+
+async fn handle_request(request: &mut Request, router: &Router) -> Response {
+    let mut response = dispatch(request, router).await;
+
+    vary_on_accept_encoding(&mut response);
+
+    response
+}
+
+/// Caches must tell clients apart by Accept-Encoding whether or not a body
+/// is compressed, so every response says so, errors included.
+fn vary_on_accept_encoding(response: &mut Response) {
+    let vary = response.headers.get_list(headers::VARY);
+
+    if !vary.iter().any(|field| *field == "*" || field.eq_ignore_ascii_case(headers::ACCEPT_ENCODING.as_str())) {
+        response.append_header(headers::VARY, headers::ACCEPT_ENCODING.as_str());
+    }
+}
+
+/// Decodes the body and runs the middleware chain and the matching handler.
+async fn dispatch(request: &mut Request, router: &Router) -> Response {
+    request.router = Some(router.clone());
+
+    let mut response = Response::from(&*request);
+
+    if let Err(err) = request.decode_body() {
+        eprintln!("Rejecting request body: {}", err);
+
+        response.status = match err {
+            DecodeError::Unsupported(_) => HttpCode::UnsupportedMediaType,
+            DecodeError::TooLarge(_) => HttpCode::ContentTooLarge,
+            DecodeError::Invalid(..) => HttpCode::BadRequest,
+        };
+
+        // tells the client which codings it can use instead (RFC 9110, section 12.5.3)
+        if response.status == HttpCode::UnsupportedMediaType {
+            let supported = Encoding::supported().iter().map(|encoding| encoding.token()).collect::<Vec<&str>>().join(", ");
+
+            response.set_header(headers::ACCEPT_ENCODING, supported);
         }
 
-        response.write_to(_stream, None);
+        return response;
+    }
 
-        return;
+    let handler = match router.get_handler(&request.method, request.url.as_str()) {
+        RouteMatch::NotFound => not_found_handler(),
+
+        RouteMatch::MethodNotAllowed(allowed_methods) => allow_handler(HttpCode::MethodNotAllowed, allowed_methods),
+
+        RouteMatch::Options(allowed_methods) => allow_handler(HttpCode::Ok, allowed_methods),
+
+        RouteMatch::Found(handler, params) => {
+            request.params = params;
+
+            handler
+        }
+    };
+
+    let middleware = router.middleware_chain();
+
+    let result = Next::new(&middleware, &handler).run(request, &mut response).await;
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+
+        response.status = match err.downcast_ref::<ParamError>() {
+            Some(_) => HttpCode::BadRequest,
+            None => HttpCode::InternalServerError,
+        };
     }
 
-    _stream.write_all(String::from("HTTP/1.1 500 Internal Server Error/\r\n\r\n").as_bytes()).expect("Failed to send Response to client");
+    response
 }
 
-fn encode_gzip(content: &Vec<u8>) -> Vec<u8> {
-    let mut e = GzEncoder::new(Vec::new(), Compression::default());
+fn not_found_handler() -> RequestHandler {
+    Arc::new(|_req, res| Box::pin(async move {
+        res.status = HttpCode::NotFound;
+
+        Ok(())
+    }))
+}
+
+/// Answers with `status` and an Allow header, for 405s and automatic OPTIONS.
+fn allow_handler(status: HttpCode, allowed_methods: Vec<HTTPMethod>) -> RequestHandler {
+    let allow = allowed_methods.iter().map(|method| method.to_string()).collect::<Vec<String>>().join(", ");
+
+    Arc::new(move |_req, res| {
+        let status = status.clone();
+        let allow = allow.clone();
 
-    e.write_all(content).expect("Failed to Compress Data");
+        Box::pin(async move {
+            res.status = status;
 
-    let compressed_bytes = e.finish().expect("Failed to finish compression");
+            res.set_header(headers::ALLOW, allow);
 
-    compressed_bytes
+            Ok(())
+        })
+    })
 }
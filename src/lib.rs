@@ -0,0 +1,14 @@
+pub mod body;
+mod chunked;
+pub mod compression;
+mod constants;
+pub mod headers;
+pub mod http_server;
+pub mod middleware;
+pub mod options;
+pub mod request;
+mod request_reader;
+pub mod response;
+pub mod router;
+pub mod shutdown;
+pub mod url;
@@ -0,0 +1,62 @@
+use crate::compression::{is_compressible, negotiate};
+use crate::headers;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::{BoxFuture, HandlerResult, RequestHandler};
+
+use std::sync::Arc;
+
+pub type Middleware = Arc<dyn for<'a> Fn(&'a Request, &'a mut Response, Next<'a>) -> BoxFuture<'a, HandlerResult> + Send + Sync>;
+
+/// The rest of the chain after the current middleware: the middleware that
+/// were registered after it, followed by the route handler.
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    handler: &'a RequestHandler,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middleware: &'a [Middleware], handler: &'a RequestHandler) -> Self {
+        Self { middleware, handler }
+    }
+
+    pub fn run<'b>(self, request: &'b Request, response: &'b mut Response) -> BoxFuture<'b, HandlerResult>
+    where
+        'a: 'b,
+    {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware(request, response, Next::new(rest, self.handler)),
+
+            None => (self.handler)(request, response),
+        }
+    }
+}
+
+/// Compresses response bodies with the encoding the client weights highest in
+/// Accept-Encoding. Only in-memory bodies are compressed; empty and already
+/// encoded ones, those below `--compression-min-size` and already compressed
+/// media types are sent as is, like files and streams.
+pub fn compression<'a>(request: &'a Request, response: &'a mut Response, next: Next<'a>) -> BoxFuture<'a, HandlerResult> {
+    Box::pin(async move {
+        next.run(request, response).await?;
+
+        let Some(body) = response.body.as_bytes() else { return Ok(()) };
+
+        let skip = body.is_empty()
+            || body.len() < request.server_options.compression_min_size
+            || response.headers.contains(headers::CONTENT_ENCODING)
+            || !is_compressible(response.headers.get(headers::CONTENT_TYPE));
+
+        if skip {
+            return Ok(());
+        }
+
+        if let Some(encoding) = negotiate(&request.headers.get_list(headers::ACCEPT_ENCODING)) {
+            let body = encoding.encode(body)?;
+
+            response.set_encoded_body(body, encoding.token());
+        }
+
+        Ok(())
+    })
+}
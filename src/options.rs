@@ -12,4 +12,56 @@ pub struct Options {
 
     #[arg(short = 'd', long = "directory", default_value = "/tmp")]
     pub files_directory: String,
-}
\ No newline at end of file
+
+    /// Seconds an idle keep-alive connection is kept open waiting for the next request
+    #[arg(long, default_value_t = 5)]
+    pub keep_alive_timeout: u64,
+
+    /// Maximum number of requests served on a single connection (0 for no limit)
+    #[arg(long, default_value_t = 100)]
+    pub max_requests_per_connection: usize,
+
+    /// Maximum size in bytes of the request line and headers
+    #[arg(long, default_value_t = 8 * 1024)]
+    pub max_header_size: usize,
+
+    /// Maximum size in bytes of a request body
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    pub max_body_size: usize,
+
+    /// Maximum size in bytes of a request body once its Content-Encoding is decoded
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    pub max_decoded_body_size: usize,
+
+    /// Seconds in-flight requests are given to finish after a shutdown is requested
+    #[arg(long, default_value_t = 30)]
+    pub shutdown_grace_period: u64,
+
+    /// Smallest response body in bytes that is compressed (0 compresses every
+    /// non-empty body, which the codecrafters tests expect)
+    #[arg(long, default_value_t = 0)]
+    pub compression_min_size: usize,
+
+    /// Print the registered routes and exit instead of starting the server
+    #[arg(long)]
+    pub print_routes: bool,
+
+    /// Value of the Server header sent with every response
+    #[arg(long, default_value = concat!("codecrafters-http-server/", env!("CARGO_PKG_VERSION")))]
+    pub server_header: String,
+
+    /// Do not send the Server header
+    #[arg(long)]
+    pub no_server_header: bool,
+}
+
+impl Options {
+    /// The Server header to send, unless it is turned off.
+    pub fn server(&self) -> Option<String> {
+        if self.no_server_header {
+            return None;
+        }
+
+        Some(self.server_header.clone())
+    }
+}
@@ -1,9 +1,20 @@
-use crate::constants::CRLF;
+use crate::compression::{self, DecodeError};
+use crate::constants::{CRLF, HEAD_TERMINATOR, MAX_FIELD_SIZE};
+use crate::headers::{self, is_token, parse_field_line, HeaderMap};
 use crate::options::Options;
+use crate::request_reader::find;
+use crate::router::{ParamError, Params, Router, UrlError};
+use crate::url::{decode_path_segment, normalize_path, Query};
+
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::str::{FromStr, Utf8Error};
+use thiserror::Error;
 
-#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, Ord, PartialOrd)]
 pub enum HTTPMethod {
     GET,
     POST,
@@ -30,147 +41,377 @@ impl HTTPMethod {
 
 impl Display for HTTPMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.text().to_string())
+        write!(f, "{}", self.text())
     }
 }
 
-impl From<&str> for HTTPMethod {
-    fn from(value: &str) -> Self {
+impl FromStr for HTTPMethod {
+    type Err = ParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         match value {
-            "GET" => HTTPMethod::GET,
-            "PUT" => HTTPMethod::PUT,
-            "POST" => HTTPMethod::POST,
-            "HEAD" => HTTPMethod::HEAD,
-            "PATCH" => HTTPMethod::PATCH,
-            "DELETE" => HTTPMethod::DELETE,
-            "OPTIONS" => HTTPMethod::OPTIONS,
-            _ => panic!("Invalid HTTP method"),
+            "GET" => Ok(HTTPMethod::GET),
+            "PUT" => Ok(HTTPMethod::PUT),
+            "POST" => Ok(HTTPMethod::POST),
+            "HEAD" => Ok(HTTPMethod::HEAD),
+            "PATCH" => Ok(HTTPMethod::PATCH),
+            "DELETE" => Ok(HTTPMethod::DELETE),
+            "OPTIONS" => Ok(HTTPMethod::OPTIONS),
+            _ => Err(ParseError::UnknownMethod(value.to_string())),
         }
     }
 }
 
+/// method, url, query, protocol, protocol version and host
+type RequestLine = (HTTPMethod, String, Query, String, String, String);
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("malformed request line: {0:?}")]
+    BadRequestLine(String),
+
+    #[error("malformed header: {0:?}")]
+    BadHeader(String),
+
+    #[error("path is not UTF-8 once percent-decoded: {0}")]
+    BadPath(String),
+
+    #[error("unsupported HTTP version: {0}")]
+    UnsupportedVersion(String),
+
+    #[error("unknown method: {0}")]
+    UnknownMethod(String),
+
+    #[error("field exceeds the limit of {0} bytes")]
+    FieldTooLarge(usize),
+}
+
 #[derive(Debug, Clone)]
 pub struct Request {
+    /// the request path after normalization, still percent-encoded
     pub url: String,
     pub protocol: String,
     pub method: HTTPMethod,
     pub host: Option<String>,
     pub server_options: Options,
     pub protocol_version: String,
-    pub query: HashMap<String, String>,
-    pub params: HashMap<String, String>,
-    pub headers: HashMap<String, String>,
-
-    pub body: String,
+    pub query: Query,
+    pub params: Params,
+    /// the router that matched the request, set before the handler runs
+    pub router: Option<Router>,
+    pub headers: HeaderMap,
+    pub trailers: HeaderMap,
+
+    pub body: Bytes,
 }
 
 impl Request {
-    pub fn new(request: String, options: Options) -> Self {
-        let params: HashMap<String, String> = HashMap::new();
-        let mut headers: HashMap<String, String> = HashMap::new();
-
-        let request_parts = request.split(CRLF).collect::<Vec<&str>>();
+    /// Parses a raw request message: the request line, the header block and
+    /// whatever follows the blank line as the body.
+    pub fn parse(request: &[u8], options: Options) -> Result<Self, ParseError> {
+        let (head, body) = match find(request, HEAD_TERMINATOR) {
+            None => (request, &[][..]),
+            Some(position) => (&request[..position], &request[position + HEAD_TERMINATOR.len()..]),
+        };
 
-        // parse first part
-        let first_line = request_parts[0];
+        let head = String::from_utf8_lossy(head);
 
-        let (method, url, query, protocol, protocol_version, host) = Self::parse_first_line(first_line);
+        let mut lines = head.split(CRLF);
 
-        // parse headers
-        let mut i = 1;
+        // parse first line
+        let first_line = lines.next().unwrap_or_default();
 
-        while i < request_parts.len() && request_parts[i] != "" {
-            let header_parts: Vec<&str> = request_parts[i].split(": ").collect();
+        if first_line.len() > MAX_FIELD_SIZE {
+            return Err(ParseError::FieldTooLarge(MAX_FIELD_SIZE));
+        }
 
-            let header_name = header_parts[0].to_string();
+        let (method, url, query, protocol, protocol_version, host) = Self::parse_first_line(first_line)?;
 
-            let header_value = header_parts[1].to_string();
+        // parse headers
+        let mut headers = HeaderMap::new();
 
-            headers.insert(header_name, header_value);
+        for line in lines {
+            if line.is_empty() {
+                break;
+            }
 
-            i += 1;
-        }
+            if line.len() > MAX_FIELD_SIZE {
+                return Err(ParseError::FieldTooLarge(MAX_FIELD_SIZE));
+            }
 
-        // parse body
-        let body_str: &str = request_parts[i + 1];
+            let (header_name, header_value) = parse_field_line(line).ok_or_else(|| ParseError::BadHeader(line.to_string()))?;
 
-        let body = body_str.trim().to_string();
+            headers.append(header_name, header_value);
+        }
 
-        let mut final_host = match headers.get("Host") {
-            None => None,
-            Some(host) => Some(host.clone()),
-        };
+        let mut final_host = headers.get(headers::HOST).map(str::to_string);
 
         if !host.is_empty() {
             final_host = Some(host);
         };
 
-        Self {
+        let mut request = Self {
             url,
-            body,
             query,
-            params,
+            method,
             headers,
             protocol,
             protocol_version,
+            body: Bytes::new(),
             host: final_host,
+            params: Params::default(),
+            router: None,
+            trailers: HeaderMap::new(),
             server_options: options,
-            method: HTTPMethod::from(method.as_str()),
+        };
+
+        request.set_body(Bytes::copy_from_slice(body));
+
+        Ok(request)
+    }
+
+    pub fn set_body(&mut self, body: impl Into<Bytes>) {
+        self.body = body.into();
+    }
+
+    /// Decodes a body sent with a Content-Encoding, so handlers only ever see
+    /// the decoded bytes. Fails for unknown codings and for bodies that decode
+    /// to more than `--max-decoded-body-size` bytes.
+    pub fn decode_body(&mut self) -> Result<(), DecodeError> {
+        let codings = self.headers.get_list(headers::CONTENT_ENCODING);
+
+        if codings.is_empty() {
+            return Ok(());
         }
+
+        let body = compression::decode(&codings, &self.body, self.server_options.max_decoded_body_size)?;
+
+        self.headers.remove(headers::CONTENT_ENCODING);
+
+        self.headers.insert(headers::CONTENT_LENGTH, body.len().to_string());
+
+        self.set_body(body);
+
+        Ok(())
     }
 
-    fn parse_first_line(first_line: &str) -> (String, String, HashMap<String, String>, String, String, String) {
-        let first_line_parts = first_line.split_whitespace().collect::<Vec<&str>>();
+    pub fn text(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
 
-        let method = first_line_parts[0].trim().to_string();
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
 
-        let (url, query, host) = Self::parse_request_target(&first_line_parts[1]);
+    /// Decodes an `application/x-www-form-urlencoded` body, keeping the last value of repeated keys.
+    pub fn form(&self) -> HashMap<String, String> {
+        form_urlencoded::parse(&self.body).into_owned().collect()
+    }
 
-        let (protocol, protocol_version) = Self::parse_protocol(&first_line_parts);
+    /// Parses a path param, e.g. `req.param::<u64>("id")`. Returning the error
+    /// from a handler answers the request with 400.
+    pub fn param<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        self.params.parse(name)
+    }
 
-        (method, url, query, protocol, protocol_version, host)
+    /// Builds the URL of a named route, see `Router::url_for`.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)], query: &[(&str, &str)]) -> Result<String, UrlError> {
+        match &self.router {
+            Some(router) => router.url_for(name, params, query),
+            None => Err(UrlError::UnknownRoute(name.to_string())),
+        }
     }
 
-    fn parse_protocol(first_line_parts: &Vec<&str>) -> (String, String) {
-        let protocol_and_version = first_line_parts[2].trim().split('/').collect::<Vec<&str>>();
+    pub fn is_keep_alive(&self) -> bool {
+        let tokens = self.headers.get_list(headers::CONNECTION);
 
-        let protocol = protocol_and_version[0].to_string();
+        if tokens.iter().any(|token| token.eq_ignore_ascii_case("close")) {
+            return false;
+        }
 
-        let protocol_version = protocol_and_version[1].to_string();
+        if tokens.iter().any(|token| token.eq_ignore_ascii_case("keep-alive")) {
+            return true;
+        }
 
-        (protocol, protocol_version)
+        // HTTP/1.1 connections are persistent by default, HTTP/1.0 ones are not
+        self.protocol_version != "1.0"
     }
 
-    fn parse_request_target(request_target: &str) -> (String, HashMap<String, String>, String) {
-        let host = String::new();
+    fn parse_first_line(first_line: &str) -> Result<RequestLine, ParseError> {
+        let first_line_parts = first_line.split_whitespace().collect::<Vec<&str>>();
 
-        let mut query: HashMap<String, String> = HashMap::new();
+        if first_line_parts.len() != 3 {
+            return Err(ParseError::BadRequestLine(first_line.to_string()));
+        }
 
-        let full_url = request_target.trim().to_string();
+        let method_name = first_line_parts[0];
 
-        let url_splits = full_url.split('?').collect::<Vec<&str>>();
+        if !is_token(method_name) {
+            return Err(ParseError::BadRequestLine(first_line.to_string()));
+        }
+
+        let (protocol, protocol_version) = Self::parse_protocol(first_line_parts[2])
+            .ok_or_else(|| ParseError::BadRequestLine(first_line.to_string()))?;
+
+        if !protocol_version.starts_with("1.") {
+            return Err(ParseError::UnsupportedVersion(protocol_version));
+        }
+
+        let method = method_name.parse::<HTTPMethod>()?;
+
+        let request_target = first_line_parts[1];
+
+        if !request_target.starts_with('/') && request_target != "*" {
+            return Err(ParseError::BadRequestLine(first_line.to_string()));
+        }
+
+        let (url, query, host) = Self::parse_request_target(request_target);
 
-        let url = url_splits[0].to_string();
+        if url.split('/').any(|segment| decode_path_segment(segment).is_none()) {
+            return Err(ParseError::BadPath(url));
+        }
+
+        Ok((method, url, query, protocol, protocol_version, host))
+    }
+
+    fn parse_protocol(protocol_and_version: &str) -> Option<(String, String)> {
+        let (protocol, protocol_version) = protocol_and_version.split_once('/')?;
+
+        if protocol != "HTTP" {
+            return None;
+        }
+
+        let (major, minor) = protocol_version.split_once('.')?;
+
+        let is_number = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+
+        if !is_number(major) || !is_number(minor) {
+            return None;
+        }
+
+        Some((protocol.to_string(), protocol_version.to_string()))
+    }
+
+    fn parse_request_target(request_target: &str) -> (String, Query, String) {
+        let host = String::new();
 
         // TODO: handle absolute form
         // let is_absolute_form = url.starts_with("http://");
 
-        if url_splits.len() > 1 {
-            let query_string = url_splits[1].to_string();
+        let (path, query_string) = request_target.trim().split_once('?').unwrap_or((request_target.trim(), ""));
 
-            if !query_string.is_empty() {
-                for param in query_string.split('&') {
-                    let parts = param.split('=').collect::<Vec<&str>>();
+        let url = match path {
+            "*" => path.to_string(),
+            _ => normalize_path(path),
+        };
 
-                    if parts.len() == 2 {
-                        let key = parts[0].to_string();
-                        let value = parts[1].to_string();
-                        query.insert(key, value);
-                    }
-                }
-            }
-        }
+        let query = Query::parse(query_string);
 
         (url, query, host)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Encoding;
+    use clap::Parser;
+
+    fn parse(request: &str) -> Result<Request, ParseError> {
+        Request::parse(request.as_bytes(), Options::parse_from(["test"]))
+    }
+
+    #[test]
+    fn parses_the_request_line_headers_and_body() {
+        let request = parse("POST /a/./b?x=1&x=2 HTTP/1.1\r\nHost: example.com\r\nX-Tag: a\r\nx-tag: b\r\n\r\nbody").unwrap();
+
+        assert_eq!(request.method, HTTPMethod::POST);
+
+        assert_eq!(request.url, "/a/b");
+
+        assert_eq!(request.query.get_all("x").collect::<Vec<&str>>(), ["1", "2"]);
+
+        assert_eq!(request.protocol_version, "1.1");
+
+        assert_eq!(request.host.as_deref(), Some("example.com"));
+
+        assert_eq!(request.headers.get_all("X-Tag"), ["a", "b"]);
+
+        assert_eq!(&request.body[..], b"body");
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        for line in ["GET /", "GET / HTTP/1.1 extra", "GET a HTTP/1.1", "GET / HTTPS/1.1", "GET / HTTP/1", "GET / HTTP/x.1", "G(T / HTTP/1.1"] {
+            let result = parse(&format!("{}\r\n\r\n", line));
+
+            assert!(matches!(result, Err(ParseError::BadRequestLine(_))), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for header in ["No-Colon", "Bad Name: value", " Host: example.com", ": value"] {
+            let result = parse(&format!("GET / HTTP/1.1\r\n{}\r\n\r\n", header));
+
+            assert!(matches!(result, Err(ParseError::BadHeader(_))), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn rejects_paths_that_do_not_decode_to_utf8() {
+        assert!(matches!(parse("GET /echo/%C3%28 HTTP/1.1\r\n\r\n"), Err(ParseError::BadPath(path)) if path == "/echo/%C3%28"));
+
+        assert_eq!(parse("GET /echo/caf%C3%A9 HTTP/1.1\r\n\r\n").unwrap().url, "/echo/caf%C3%A9");
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        assert!(matches!(parse("GET / HTTP/2.0\r\n\r\n"), Err(ParseError::UnsupportedVersion(version)) if version == "2.0"));
+
+        assert!(matches!(parse("GET / HTTP/0.9\r\n\r\n"), Err(ParseError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn rejects_unknown_methods() {
+        assert!(matches!(parse("BREW / HTTP/1.1\r\n\r\n"), Err(ParseError::UnknownMethod(method)) if method == "BREW"));
+
+        assert!(matches!(parse("get / HTTP/1.1\r\n\r\n"), Err(ParseError::UnknownMethod(_))));
+    }
+
+    #[test]
+    fn rejects_oversized_fields() {
+        let long_url = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_FIELD_SIZE));
+
+        assert!(matches!(parse(&long_url), Err(ParseError::FieldTooLarge(MAX_FIELD_SIZE))));
+
+        let long_header = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(MAX_FIELD_SIZE));
+
+        assert!(matches!(parse(&long_header), Err(ParseError::FieldTooLarge(MAX_FIELD_SIZE))));
+    }
+
+    #[test]
+    fn decode_body_replaces_the_body_and_its_headers() {
+        let body = Encoding::Gzip.encode(b"decoded").unwrap();
+
+        let head = format!("POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len());
+
+        let mut request = Request::parse(&[head.as_bytes(), &body].concat(), Options::parse_from(["test"])).unwrap();
+
+        request.decode_body().unwrap();
+
+        assert_eq!(&request.body[..], b"decoded");
+
+        assert!(!request.headers.contains(headers::CONTENT_ENCODING));
+
+        assert_eq!(request.headers.get(headers::CONTENT_LENGTH), Some("7"));
+    }
+
+    #[test]
+    fn decode_body_rejects_unknown_codings() {
+        let mut request = parse("POST / HTTP/1.1\r\nContent-Encoding: compress\r\n\r\nbody").unwrap();
+
+        assert!(matches!(request.decode_body(), Err(DecodeError::Unsupported(_))));
+
+        assert_eq!(&request.body[..], b"body");
+    }
+}
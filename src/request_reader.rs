@@ -0,0 +1,356 @@
+use crate::chunked::parse_chunk_size;
+use crate::constants::HEAD_TERMINATOR;
+use crate::headers::parse_field_line;
+
+use std::io::ErrorKind;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const READ_CHUNK_SIZE: usize = 1024;
+
+const LINE_TERMINATOR: &[u8] = b"\r\n";
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("connection closed before the request was complete")]
+    UnexpectedEof,
+
+    #[error("request headers exceed the limit of {0} bytes")]
+    HeadersTooLarge(usize),
+
+    #[error("request body exceeds the limit of {0} bytes")]
+    BodyTooLarge(usize),
+
+    #[error("invalid Content-Length header")]
+    InvalidContentLength,
+
+    #[error("unsupported Transfer-Encoding: {0}")]
+    UnsupportedTransferEncoding(String),
+
+    #[error("request has both Transfer-Encoding and Content-Length")]
+    ConflictingFraming,
+
+    #[error("malformed chunked body")]
+    InvalidChunk,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Trailers = Vec<(String, String)>;
+
+pub struct RawRequest {
+    pub head: Vec<u8>,
+    pub body: Vec<u8>,
+    pub trailers: Trailers,
+}
+
+enum BodyFraming {
+    Length(usize),
+    Chunked,
+}
+
+/// Reads requests off a stream one at a time, keeping any bytes that belong
+/// to the next request around for the following call.
+pub struct RequestReader {
+    buffer: Vec<u8>,
+    read_timeout: Duration,
+    max_header_size: usize,
+    max_body_size: usize,
+}
+
+impl RequestReader {
+    pub fn new(read_timeout: Duration, max_header_size: usize, max_body_size: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            read_timeout,
+            max_header_size,
+            max_body_size,
+        }
+    }
+
+    /// Whether part of the next request has already been read off the stream.
+    pub fn has_buffered_data(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Returns `Ok(None)` when the client closed the connection between requests.
+    pub async fn read_request<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> Result<Option<RawRequest>, ReadError> {
+        let head_end = loop {
+            if let Some(position) = find(&self.buffer, HEAD_TERMINATOR) {
+                break position + HEAD_TERMINATOR.len();
+            }
+
+            if self.buffer.len() > self.max_header_size {
+                return Err(ReadError::HeadersTooLarge(self.max_header_size));
+            }
+
+            if self.fill(stream).await? == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+
+                return Err(ReadError::UnexpectedEof);
+            }
+        };
+
+        if head_end > self.max_header_size {
+            return Err(ReadError::HeadersTooLarge(self.max_header_size));
+        }
+
+        let head = self.buffer.drain(..head_end).collect::<Vec<u8>>();
+
+        let (body, trailers) = match body_framing(&head)? {
+            BodyFraming::Length(content_length) => (self.read_sized_body(stream, content_length).await?, Vec::new()),
+
+            BodyFraming::Chunked => self.read_chunked_body(stream).await?,
+        };
+
+        Ok(Some(RawRequest { head, body, trailers }))
+    }
+
+    async fn read_sized_body<R: AsyncRead + Unpin>(&mut self, stream: &mut R, content_length: usize) -> Result<Vec<u8>, ReadError> {
+        if content_length > self.max_body_size {
+            return Err(ReadError::BodyTooLarge(self.max_body_size));
+        }
+
+        self.fill_to(stream, content_length).await?;
+
+        Ok(self.buffer.drain(..content_length).collect())
+    }
+
+    async fn read_chunked_body<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> Result<(Vec<u8>, Trailers), ReadError> {
+        let mut body = Vec::new();
+
+        loop {
+            let line = self.read_line(stream).await?;
+
+            let chunk_size = parse_chunk_size(&line).ok_or(ReadError::InvalidChunk)?;
+
+            if chunk_size == 0 {
+                break;
+            }
+
+            // body.len() never exceeds max_body_size, and comparing this way
+            // cannot overflow for sizes close to usize::MAX
+            if chunk_size > self.max_body_size - body.len() {
+                return Err(ReadError::BodyTooLarge(self.max_body_size));
+            }
+
+            self.fill_to(stream, chunk_size + LINE_TERMINATOR.len()).await?;
+
+            body.extend(self.buffer.drain(..chunk_size));
+
+            if !self.buffer.starts_with(LINE_TERMINATOR) {
+                return Err(ReadError::InvalidChunk);
+            }
+
+            self.buffer.drain(..LINE_TERMINATOR.len());
+        }
+
+        let mut trailers = Vec::new();
+
+        let mut trailers_size = 0;
+
+        loop {
+            let line = self.read_line(stream).await?;
+
+            if line.is_empty() {
+                break;
+            }
+
+            trailers_size += line.len();
+
+            if trailers_size > self.max_header_size {
+                return Err(ReadError::HeadersTooLarge(self.max_header_size));
+            }
+
+            let line = String::from_utf8_lossy(&line);
+
+            let (name, value) = parse_field_line(&line).ok_or(ReadError::InvalidChunk)?;
+
+            trailers.push((name.to_string(), value.to_string()));
+        }
+
+        Ok((body, trailers))
+    }
+
+    /// Reads a single CRLF terminated line and returns it without the terminator.
+    async fn read_line<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> Result<Vec<u8>, ReadError> {
+        loop {
+            if let Some(position) = find(&self.buffer, LINE_TERMINATOR) {
+                let mut line = self.buffer.drain(..position + LINE_TERMINATOR.len()).collect::<Vec<u8>>();
+
+                line.truncate(position);
+
+                return Ok(line);
+            }
+
+            if self.buffer.len() > self.max_header_size {
+                return Err(ReadError::HeadersTooLarge(self.max_header_size));
+            }
+
+            if self.fill(stream).await? == 0 {
+                return Err(ReadError::UnexpectedEof);
+            }
+        }
+    }
+
+    async fn fill_to<R: AsyncRead + Unpin>(&mut self, stream: &mut R, size: usize) -> Result<(), ReadError> {
+        while self.buffer.len() < size {
+            if self.fill(stream).await? == 0 {
+                return Err(ReadError::UnexpectedEof);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn fill<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> std::io::Result<usize> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+
+        let bytes_read = match tokio::time::timeout(self.read_timeout, stream.read(&mut chunk)).await {
+            Ok(result) => result?,
+            Err(_) => return Err(std::io::Error::new(ErrorKind::TimedOut, "timed out waiting for the client")),
+        };
+
+        self.buffer.extend_from_slice(&chunk[..bytes_read]);
+
+        Ok(bytes_read)
+    }
+}
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn body_framing(head: &[u8]) -> Result<BodyFraming, ReadError> {
+    let head = String::from_utf8_lossy(head);
+
+    let mut content_length = None;
+
+    let mut transfer_codings = Vec::new();
+
+    for line in head.split("\r\n").skip(1) {
+        let Some((name, value)) = parse_field_line(line) else { continue };
+
+        if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            transfer_codings.extend(value.split(',').map(|coding| coding.trim().to_lowercase()));
+
+            continue;
+        }
+
+        if !name.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+
+        // Content-Length is 1*DIGIT, parse alone would also accept a sign
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ReadError::InvalidContentLength);
+        }
+
+        let value = value.parse::<usize>().map_err(|_| ReadError::InvalidContentLength)?;
+
+        match content_length {
+            Some(previous) if previous != value => return Err(ReadError::InvalidContentLength),
+            _ => content_length = Some(value),
+        }
+    }
+
+    // a request with both may be an attempt at request smuggling, so it is
+    // rejected and the connection closed (RFC 9112, section 6.1)
+    if !transfer_codings.is_empty() && content_length.is_some() {
+        return Err(ReadError::ConflictingFraming);
+    }
+
+    if !transfer_codings.is_empty() {
+        if transfer_codings != ["chunked"] {
+            return Err(ReadError::UnsupportedTransferEncoding(transfer_codings.join(", ")));
+        }
+
+        return Ok(BodyFraming::Chunked);
+    }
+
+    Ok(BodyFraming::Length(content_length.unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(max_body_size: usize) -> RequestReader {
+        RequestReader::new(Duration::from_secs(1), 1024, max_body_size)
+    }
+
+    #[tokio::test]
+    async fn reads_a_chunked_body_and_its_trailers() {
+        let mut stream = &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\nX-Sum: 5\r\n\r\n"[..];
+
+        let request = reader(16).read_request(&mut stream).await.unwrap().unwrap();
+
+        assert_eq!(request.body, b"abcde");
+
+        assert_eq!(request.trailers, vec![("X-Sum".to_string(), "5".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_huge_second_chunk_without_overflowing() {
+        let mut stream = &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nFFFFFFFFFFFFFFFF\r\n"[..];
+
+        let result = reader(16).read_request(&mut stream).await;
+
+        assert!(matches!(result, Err(ReadError::BodyTooLarge(16))));
+    }
+
+    #[tokio::test]
+    async fn rejects_chunks_that_add_up_past_the_limit() {
+        let mut stream = &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n8\r\naaaaaaaa\r\n9\r\naaaaaaaaa\r\n0\r\n\r\n"[..];
+
+        let result = reader(16).read_request(&mut stream).await;
+
+        assert!(matches!(result, Err(ReadError::BodyTooLarge(16))));
+    }
+
+    #[tokio::test]
+    async fn reads_a_sized_body() {
+        let mut stream = &b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET"[..];
+
+        let mut reader = reader(16);
+
+        let request = reader.read_request(&mut stream).await.unwrap().unwrap();
+
+        assert_eq!(request.body, b"abc");
+
+        assert!(reader.has_buffered_data());
+    }
+
+    #[tokio::test]
+    async fn rejects_content_lengths_that_are_not_digits() {
+        for content_length in ["+3", "-3", " ", "3a", "0x3", "3, 3"] {
+            let head = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nabc", content_length);
+
+            let result = reader(16).read_request(&mut head.as_bytes()).await;
+
+            assert!(matches!(result, Err(ReadError::InvalidContentLength)), "{:?}", content_length);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_conflicting_content_lengths() {
+        let mut stream = &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd"[..];
+
+        let result = reader(16).read_request(&mut stream).await;
+
+        assert!(matches!(result, Err(ReadError::InvalidContentLength)));
+    }
+
+    #[tokio::test]
+    async fn rejects_transfer_encoding_with_content_length() {
+        let mut stream = &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /echo/smuggled HTTP/1.1\r\n\r\n"[..];
+
+        let result = reader(64).read_request(&mut stream).await;
+
+        assert!(matches!(result, Err(ReadError::ConflictingFraming)));
+    }
+}
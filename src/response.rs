@@ -1,26 +1,187 @@
+use crate::body::Body;
+use crate::headers::{self, HeaderMap, HeaderName};
 use crate::request::Request;
-use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
 use std::fmt::Display;
-use std::io::Write;
-use std::net::TcpStream;
-
-pub enum HttpCode {
-    Ok,
-    Created,
-    NotFound,
-    BadRequest,
-    InternalServerError,
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+
+/// Generates `HttpCode` and its lookups from a single table of
+/// `Variant = (code, reason phrase)` rows.
+macro_rules! status_codes {
+    ($($variant:ident = ($code:literal, $reason:literal),)+) => {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum HttpCode {
+            $($variant,)+
+
+            /// A status code missing from the registry, with its reason phrase,
+            /// built with `HttpCode::custom`
+            Custom(CustomStatus),
+        }
+
+        impl HttpCode {
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(HttpCode::$variant => $code,)+
+                    HttpCode::Custom(status) => status.code,
+                }
+            }
+
+            pub fn reason_phrase(&self) -> &str {
+                match self {
+                    $(HttpCode::$variant => $reason,)+
+                    HttpCode::Custom(status) => status.reason.as_str(),
+                }
+            }
+        }
+
+        impl TryFrom<u16> for HttpCode {
+            type Error = InvalidStatusCode;
+
+            /// Only accepts registered codes, use `HttpCode::custom` for anything else.
+            fn try_from(code: u16) -> Result<Self, Self::Error> {
+                match code {
+                    $($code => Ok(HttpCode::$variant),)+
+                    _ => Err(InvalidStatusCode(code)),
+                }
+            }
+        }
+    };
+}
+
+// IANA HTTP Status Code Registry
+status_codes! {
+    Continue = (100, "Continue"),
+    SwitchingProtocols = (101, "Switching Protocols"),
+    Processing = (102, "Processing"),
+    EarlyHints = (103, "Early Hints"),
+    Ok = (200, "OK"),
+    Created = (201, "Created"),
+    Accepted = (202, "Accepted"),
+    NonAuthoritativeInformation = (203, "Non-Authoritative Information"),
+    NoContent = (204, "No Content"),
+    ResetContent = (205, "Reset Content"),
+    PartialContent = (206, "Partial Content"),
+    MultiStatus = (207, "Multi-Status"),
+    AlreadyReported = (208, "Already Reported"),
+    ImUsed = (226, "IM Used"),
+    MultipleChoices = (300, "Multiple Choices"),
+    MovedPermanently = (301, "Moved Permanently"),
+    Found = (302, "Found"),
+    SeeOther = (303, "See Other"),
+    NotModified = (304, "Not Modified"),
+    UseProxy = (305, "Use Proxy"),
+    TemporaryRedirect = (307, "Temporary Redirect"),
+    PermanentRedirect = (308, "Permanent Redirect"),
+    BadRequest = (400, "Bad Request"),
+    Unauthorized = (401, "Unauthorized"),
+    PaymentRequired = (402, "Payment Required"),
+    Forbidden = (403, "Forbidden"),
+    NotFound = (404, "Not Found"),
+    MethodNotAllowed = (405, "Method Not Allowed"),
+    NotAcceptable = (406, "Not Acceptable"),
+    ProxyAuthenticationRequired = (407, "Proxy Authentication Required"),
+    RequestTimeout = (408, "Request Timeout"),
+    Conflict = (409, "Conflict"),
+    Gone = (410, "Gone"),
+    LengthRequired = (411, "Length Required"),
+    PreconditionFailed = (412, "Precondition Failed"),
+    ContentTooLarge = (413, "Content Too Large"),
+    UriTooLong = (414, "URI Too Long"),
+    UnsupportedMediaType = (415, "Unsupported Media Type"),
+    RangeNotSatisfiable = (416, "Range Not Satisfiable"),
+    ExpectationFailed = (417, "Expectation Failed"),
+    MisdirectedRequest = (421, "Misdirected Request"),
+    UnprocessableContent = (422, "Unprocessable Content"),
+    Locked = (423, "Locked"),
+    FailedDependency = (424, "Failed Dependency"),
+    TooEarly = (425, "Too Early"),
+    UpgradeRequired = (426, "Upgrade Required"),
+    PreconditionRequired = (428, "Precondition Required"),
+    TooManyRequests = (429, "Too Many Requests"),
+    RequestHeaderFieldsTooLarge = (431, "Request Header Fields Too Large"),
+    UnavailableForLegalReasons = (451, "Unavailable For Legal Reasons"),
+    InternalServerError = (500, "Internal Server Error"),
+    NotImplemented = (501, "Not Implemented"),
+    BadGateway = (502, "Bad Gateway"),
+    ServiceUnavailable = (503, "Service Unavailable"),
+    GatewayTimeout = (504, "Gateway Timeout"),
+    HttpVersionNotSupported = (505, "HTTP Version Not Supported"),
+    VariantAlsoNegotiates = (506, "Variant Also Negotiates"),
+    InsufficientStorage = (507, "Insufficient Storage"),
+    LoopDetected = (508, "Loop Detected"),
+    NotExtended = (510, "Not Extended"),
+    NetworkAuthenticationRequired = (511, "Network Authentication Required"),
+}
+
+#[derive(Debug, Error)]
+#[error("unregistered HTTP status code: {0}")]
+pub struct InvalidStatusCode(pub u16);
+
+/// The code and reason phrase of a `Custom` status. The fields are private
+/// so they can only be set through the checks in `HttpCode::custom`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomStatus {
+    code: u16,
+    reason: String,
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidCustomStatus {
+    #[error("status code must have three digits: {0}")]
+    OutOfRange(u16),
+
+    #[error("reason phrase contains control characters: {0:?}")]
+    InvalidReason(String),
 }
 
 impl HttpCode {
-    pub fn to_status_line(&self) -> &str {
-        match self {
-            HttpCode::Ok => "200 OK",
-            HttpCode::NotFound => "404 Not Found",
-            HttpCode::BadRequest => "400 Bad Request",
-            HttpCode::InternalServerError => "500 Internal Server Error",
-            HttpCode::Created => "201 Created",
+    /// A `Custom` status, only for codes 100 to 999 and reason phrases that
+    /// cannot break the status line (RFC 9112, section 4).
+    pub fn custom(code: u16, reason: impl Into<String>) -> Result<HttpCode, InvalidCustomStatus> {
+        let reason = reason.into();
+
+        if !(100..=999).contains(&code) {
+            return Err(InvalidCustomStatus::OutOfRange(code));
+        }
+
+        if reason.chars().any(|char| char.is_ascii_control() && char != '\t') {
+            return Err(InvalidCustomStatus::InvalidReason(reason));
         }
+
+        Ok(HttpCode::Custom(CustomStatus { code, reason }))
+    }
+
+    pub fn to_status_line(&self) -> String {
+        format!("{} {}", self.as_u16(), self.reason_phrase())
+    }
+
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.as_u16())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.as_u16())
+    }
+}
+
+impl Display for HttpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_status_line())
     }
 }
 
@@ -47,41 +208,121 @@ impl ContentType {
 
 impl Display for ContentType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.text().to_string())
+        write!(f, "{}", self.text())
     }
 }
 
 pub struct Response {
-    pub body: Vec<u8>,
+    pub body: Body,
     pub status: HttpCode,
     pub protocol: String,
     pub protocol_version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
+    /// sent as the Server header unless the handler sets one
+    pub server: Option<String>,
 }
 
 impl Response {
     pub fn new(protocol: String, protocol_version: String) -> Response {
-        let body = Vec::new();
-        let mut headers = HashMap::new();
+        let mut headers = HeaderMap::new();
 
-        headers.insert("Content-Length".to_string(), "0".to_string());
-        headers.insert("Content-Type".to_string(), ContentType::TextPlain.to_string());
+        headers.insert(headers::CONTENT_LENGTH, "0");
+        headers.insert(headers::CONTENT_TYPE, ContentType::TextPlain.to_string());
 
         Self {
-            body,
+            body: Body::empty(),
             headers,
             protocol,
             protocol_version,
             status: HttpCode::Ok,
+            server: None,
         }
     }
 
-    pub fn write_to(&mut self, stream: &mut TcpStream, body: Option<Vec<u8>>) {
-        if body.is_some() {
-            self.set_body(body.unwrap(), None);
+    pub async fn write_to(&mut self, stream: &mut TcpStream, body: Option<Vec<u8>>) -> std::io::Result<()> {
+        if let Some(body) = body {
+            self.set_body(body, None);
+        }
+
+        let body = std::mem::take(&mut self.body);
+
+        let mut writer = BufWriter::new(stream);
+
+        writer.write_all(&self.head_to_http_format()).await?;
+
+        body.write_to(&mut writer, self.is_chunked()).await?;
+
+        writer.flush().await
+    }
+
+    /// Writes the status line and headers only, keeping Content-Length as is,
+    /// for answering HEAD requests.
+    pub async fn write_head_to(&mut self, stream: &mut TcpStream) -> std::io::Result<()> {
+        self.body = Body::empty();
+
+        stream.write_all(&self.head_to_http_format()).await
+    }
+
+    /// Streams the body from `reader` using chunked transfer-encoding instead of
+    /// buffering it, for bodies whose length is not known up front.
+    pub fn set_body_stream<R: AsyncRead + Send + Unpin + 'static>(&mut self, reader: R, content_type: Option<ContentType>) {
+        self.body = Body::Stream(Box::new(reader));
+
+        self.headers.remove(headers::CONTENT_LENGTH);
+
+        self.set_header(headers::TRANSFER_ENCODING, "chunked");
+
+        self.set_content_type(content_type.unwrap_or(ContentType::TextPlain));
+    }
+
+    /// Sends the file at `path` without loading it into memory, with its size
+    /// as Content-Length.
+    pub async fn set_body_file(&mut self, path: impl AsRef<Path>, content_type: Option<ContentType>) -> std::io::Result<()> {
+        let body = Body::from_path(path).await?;
+
+        self.set_header(headers::CONTENT_LENGTH, body.len().unwrap_or_default().to_string());
+
+        self.headers.remove(headers::TRANSFER_ENCODING);
+
+        self.body = body;
+
+        self.set_content_type(content_type.unwrap_or(ContentType::OctetStream));
+
+        Ok(())
+    }
+
+    /// Whether the body is a stream of unknown length.
+    pub fn is_streaming(&self) -> bool {
+        matches!(self.body, Body::Stream(_))
+    }
+
+    /// Makes the framing headers follow the body, whatever a handler left in
+    /// them: a body of known length is sent with it as Content-Length, and a
+    /// stream is sent chunked, or delimited by closing the connection when
+    /// `chunked` is false. Returns whether the connection can stay open after
+    /// the response.
+    pub fn set_framing(&mut self, chunked: bool) -> bool {
+        if let Some(length) = self.body.len() {
+            self.headers.remove(headers::TRANSFER_ENCODING);
+
+            self.set_header(headers::CONTENT_LENGTH, length.to_string());
+
+            return true;
         }
 
-        stream.write_all(&*self.to_http_format()).expect("Failed to send Response to client");
+        self.headers.remove(headers::CONTENT_LENGTH);
+
+        if chunked {
+            self.set_header(headers::TRANSFER_ENCODING, "chunked");
+        } else {
+            self.headers.remove(headers::TRANSFER_ENCODING);
+        }
+
+        chunked
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.headers.get_list(headers::TRANSFER_ENCODING).iter().any(|coding| coding.eq_ignore_ascii_case("chunked"))
     }
 
     pub fn set_json_body(&mut self, body: String) {
@@ -93,49 +334,66 @@ impl Response {
     }
 
     pub fn set_body(&mut self, body: Vec<u8>, content_type_option: Option<ContentType>) {
-        self.body = body;
+        self.headers.remove(headers::TRANSFER_ENCODING);
 
-        let content_length = self.body.len();
-        let content_length_name = String::from("Content-Length");
+        self.set_header(headers::CONTENT_LENGTH, body.len().to_string());
 
-        self.set_header(content_length_name, content_length.to_string());
+        self.body = Body::from(body);
 
-        let content_type = content_type_option.unwrap_or_else(|| ContentType::TextPlain);
+        let content_type = content_type_option.unwrap_or(ContentType::TextPlain);
 
         self.set_content_type(content_type);
     }
 
-    pub fn set_content_type(&mut self, content_type: ContentType) {
-        let content_type_name = String::from("Content-Type");
+    /// Replaces the body with its encoded form, keeping the Content-Type, and
+    /// names `encoding` in Content-Encoding.
+    pub fn set_encoded_body(&mut self, body: Vec<u8>, encoding: &str) {
+        self.set_header(headers::CONTENT_LENGTH, body.len().to_string());
 
-        self.set_header(content_type_name, content_type.to_string());
+        self.body = Body::from(body);
+
+        self.set_header(headers::CONTENT_ENCODING, encoding);
     }
 
-    pub fn set_header(&mut self, header_name: String, header_value: String) {
-        // let header = self.headers.get(header_name.as_str());
-        //
-        // match header {
-        //     None => {
-        //         self.headers.insert(header_name, header_value);
-        //     }
-        //     Some(_) => {
-        //     }
-        // }
+    pub fn set_content_type(&mut self, content_type: ContentType) {
+        self.set_header(headers::CONTENT_TYPE, content_type.to_string());
+    }
+
+    /// Sets a header, replacing any values it had.
+    pub fn set_header(&mut self, header_name: impl Into<HeaderName>, header_value: impl Into<String>) {
         self.headers.insert(header_name, header_value);
     }
 
-    pub fn to_http_format(&self) -> Vec<u8> {
+    /// Adds a value to a header that can repeat, such as `Set-Cookie`.
+    pub fn append_header(&mut self, header_name: impl Into<HeaderName>, header_value: impl Into<String>) {
+        self.headers.append(header_name, header_value);
+    }
+
+    fn head_to_http_format(&self) -> Vec<u8> {
         let mut res: Vec<u8> = Vec::new();
 
-        res.extend_from_slice(&format!("{}/{} {}\r\n", self.protocol, self.protocol_version, self.status.to_status_line()).as_bytes());
+        res.extend_from_slice(format!("{}/{} {}\r\n", self.protocol, self.protocol_version, self.status.to_status_line()).as_bytes());
 
-        for (key, value) in &self.headers {
-            res.extend_from_slice(&format!("{}: {}\r\n", key, value).as_bytes());
+        // Date and Server always come first, the other headers follow in the
+        // order they were set
+        let date = match self.headers.get(headers::DATE) {
+            Some(date) => date.to_string(),
+            None => httpdate::fmt_http_date(SystemTime::now()),
+        };
+
+        res.extend_from_slice(format!("{}: {}\r\n", headers::DATE, date).as_bytes());
+
+        if let Some(server) = self.headers.get(headers::SERVER).or(self.server.as_deref()) {
+            res.extend_from_slice(format!("{}: {}\r\n", headers::SERVER, server).as_bytes());
         }
 
-        res.extend_from_slice("\r\n".as_bytes());
+        let is_leading = |key: &str| key.eq_ignore_ascii_case(headers::DATE.as_str()) || key.eq_ignore_ascii_case(headers::SERVER.as_str());
 
-        res.extend(&self.body);
+        for (key, value) in self.headers.iter().filter(|(key, _)| !is_leading(key)) {
+            res.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+
+        res.extend_from_slice("\r\n".as_bytes());
 
         res
     }
@@ -143,6 +401,107 @@ impl Response {
 
 impl From<&Request> for Response {
     fn from(req: &Request) -> Self {
-        Response::new(req.protocol.clone(), req.protocol_version.clone())
+        let mut response = Response::new(req.protocol.clone(), req.protocol_version.clone());
+
+        response.server = req.server_options.server();
+
+        response
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_accepts_three_digit_codes() {
+        let code = HttpCode::custom(299, "Fine\tThanks").unwrap();
+
+        assert_eq!(code.to_status_line(), "299 Fine\tThanks");
+
+        assert!(HttpCode::custom(100, "").is_ok());
+
+        assert!(HttpCode::custom(999, "Last").is_ok());
+    }
+
+    #[test]
+    fn custom_rejects_codes_out_of_range() {
+        assert!(matches!(HttpCode::custom(99, "Low"), Err(InvalidCustomStatus::OutOfRange(99))));
+
+        assert!(matches!(HttpCode::custom(1000, "High"), Err(InvalidCustomStatus::OutOfRange(1000))));
+    }
+
+    #[test]
+    fn custom_rejects_line_breaks_in_the_reason() {
+        assert!(matches!(HttpCode::custom(299, "OK\r\nSet-Cookie: a=b"), Err(InvalidCustomStatus::InvalidReason(_))));
+
+        assert!(matches!(HttpCode::custom(299, "OK\n"), Err(InvalidCustomStatus::InvalidReason(_))));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn streams_are_chunked_even_without_the_header() {
+        let mut response = Response::new("HTTP".to_string(), "1.1".to_string());
+
+        response.set_body_stream(&b"streamed"[..], None);
+
+        response.headers.remove(headers::TRANSFER_ENCODING);
+
+        assert!(response.set_framing(true));
+
+        assert!(response.is_chunked());
+
+        response.body = Body::Stream(Box::new(&b"set directly"[..]));
+
+        response.set_header(headers::CONTENT_LENGTH, "12");
+
+        assert!(response.set_framing(true));
+
+        assert!(response.is_chunked());
+
+        assert!(!response.headers.contains(headers::CONTENT_LENGTH));
+    }
+
+    #[test]
+    fn streams_close_the_connection_when_chunked_is_not_allowed() {
+        let mut response = Response::new("HTTP".to_string(), "1.0".to_string());
+
+        response.set_body_stream(&b"streamed"[..], None);
+
+        assert!(!response.set_framing(false));
+
+        assert!(!response.is_chunked());
+    }
+
+    #[test]
+    fn bodies_set_directly_get_their_length() {
+        let mut response = Response::new("HTTP".to_string(), "1.1".to_string());
+
+        response.set_body_string("a much longer body".to_string(), None);
+
+        response.body = "hello".into();
+
+        assert!(response.set_framing(true));
+
+        assert_eq!(response.headers.get(headers::CONTENT_LENGTH), Some("5"));
+
+        response.body = Body::empty();
+
+        assert!(response.set_framing(true));
+
+        assert_eq!(response.headers.get(headers::CONTENT_LENGTH), Some("0"));
+    }
+
+    #[test]
+    fn other_bodies_are_never_chunked() {
+        let mut response = Response::new("HTTP".to_string(), "1.1".to_string());
+
+        response.set_body_string("sized".to_string(), None);
+
+        response.set_header(headers::TRANSFER_ENCODING, "chunked");
+
+        assert!(response.set_framing(true));
+
+        assert!(!response.is_chunked());
+
+        assert_eq!(response.headers.get(headers::CONTENT_LENGTH), Some("5"));
+    }
+}
@@ -1,88 +1,688 @@
+use crate::middleware::{Middleware, Next};
 use crate::request::{HTTPMethod, Request};
 use crate::response::Response;
+use crate::url::{decode_path_segment, encode_path_segment, encode_query};
 
 use regex::Regex;
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
+use thiserror::Error;
 
-pub type RequestHandler = fn(&Request, &mut Response) -> Result<(), Box<dyn Error>>;
+pub type HandlerResult = Result<(), Box<dyn Error + Send + Sync>>;
 
-#[derive(Default, Debug)]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub type RequestHandler = Arc<dyn for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync>;
+
+/// Path parameters captured by a route, in the order they appear in its pattern.
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn insert(&mut self, name: String, value: String) {
+        match self.values.iter_mut().find(|(key, _)| *key == name) {
+            Some(entry) => entry.1 = value,
+            None => self.values.push((name, value)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self.get(name).ok_or_else(|| ParamError::Missing(name.to_string()))?;
+
+        value.parse::<T>().map_err(|_| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+            type_name: std::any::type_name::<T>(),
+        })
+    }
+}
+
+/// Returned by typed param lookups; the server answers it with 400.
+#[derive(Debug, Error)]
+pub enum ParamError {
+    #[error("missing path parameter: {0}")]
+    Missing(String),
+
+    #[error("path parameter {name} = {value:?} is not a valid {type_name}")]
+    Invalid { name: String, value: String, type_name: &'static str },
+}
+
+/// A route that cannot be registered, reported by `Router::validate` at startup.
+#[derive(Debug, Clone, Error)]
+pub enum RouteError {
+    #[error("{method} {pattern} is already registered")]
+    Duplicate { method: HTTPMethod, pattern: String },
+
+    #[error("{method} {pattern} matches the same paths as {method} {existing}")]
+    Ambiguous { method: HTTPMethod, pattern: String, existing: String },
+
+    #[error("param {name} is used more than once in route {pattern}")]
+    DuplicateParam { name: String, pattern: String },
+
+    #[error("catch-all segment must be the last one in route {0}")]
+    CatchAllNotLast(String),
+
+    #[error("invalid param pattern {constraint} in route {pattern}: {message}")]
+    InvalidPattern { constraint: String, pattern: String, message: String },
+
+    #[error("malformed param segment {segment} in route {pattern}, names are lower-case letters, digits and _")]
+    InvalidSegment { segment: String, pattern: String },
+
+    #[error("param constraint {constraint} in route {pattern} may overlap {existing} at the same position")]
+    OverlappingConstraints { constraint: String, existing: String, pattern: String },
+
+    #[error("unknown param type {type_name} in route {pattern}")]
+    UnknownType { type_name: String, pattern: String },
+
+    #[error("route name {name} is already used by {pattern}")]
+    DuplicateName { name: String, pattern: String },
+
+    #[error("no route was registered before naming it {0}")]
+    NothingToName(String),
+}
+
+/// Returned by `Router::url_for` when no URL can be built for a route.
+#[derive(Debug, Error)]
+pub enum UrlError {
+    #[error("no route named {0}")]
+    UnknownRoute(String),
+
+    #[error("missing value for param {param} of route {route}")]
+    MissingParam { route: String, param: String },
+
+    #[error("{value:?} is not a valid value for param {param} of route {route}")]
+    InvalidParam { route: String, param: String, value: String },
+}
+
+/// A registered route as listed by `Router::routes`.
+#[derive(Debug, Clone)]
+pub struct RouteInfo {
+    pub method: HTTPMethod,
+    pub pattern: String,
+    pub param_names: Vec<String>,
+    pub name: Option<String>,
+}
+
+/// Restricts the values a param segment matches, from `:name(regex)` or `{name:type}`.
+#[derive(Clone)]
+enum Constraint {
+    Pattern(Regex),
+    Type(String, fn(&str) -> bool),
+}
+
+impl Constraint {
+    fn pattern(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(&format!("^(?:{})$", pattern)).map(Constraint::Pattern)
+    }
+
+    fn of_type(type_name: &str) -> Option<Self> {
+        let validator: fn(&str) -> bool = match type_name {
+            "u8" => |value| value.parse::<u8>().is_ok(),
+            "u16" => |value| value.parse::<u16>().is_ok(),
+            "u32" => |value| value.parse::<u32>().is_ok(),
+            "u64" => |value| value.parse::<u64>().is_ok(),
+            "u128" => |value| value.parse::<u128>().is_ok(),
+            "usize" => |value| value.parse::<usize>().is_ok(),
+            "i8" => |value| value.parse::<i8>().is_ok(),
+            "i16" => |value| value.parse::<i16>().is_ok(),
+            "i32" => |value| value.parse::<i32>().is_ok(),
+            "i64" => |value| value.parse::<i64>().is_ok(),
+            "i128" => |value| value.parse::<i128>().is_ok(),
+            "isize" => |value| value.parse::<isize>().is_ok(),
+            "f32" => |value| value.parse::<f32>().is_ok(),
+            "f64" => |value| value.parse::<f64>().is_ok(),
+            "bool" => |value| value.parse::<bool>().is_ok(),
+            "string" => |_| true,
+            _ => return None,
+        };
+
+        Some(Constraint::Type(type_name.to_string(), validator))
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Constraint::Pattern(regex) => regex.is_match(value),
+            Constraint::Type(_, validator) => validator(value),
+        }
+    }
+
+    fn source(&self) -> &str {
+        match self {
+            Constraint::Pattern(regex) => regex.as_str(),
+            Constraint::Type(type_name, _) => type_name,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ParamChild {
+    constraint: Option<Constraint>,
+    node: Arc<TrieNode>,
+}
+
+pub enum RouteMatch {
+    Found(RequestHandler, Params),
+
+    /// The path is routed but not for this method; holds the methods that are.
+    MethodNotAllowed(Vec<HTTPMethod>),
+
+    /// An `OPTIONS` request without an explicit handler; holds the methods allowed on the path.
+    Options(Vec<HTTPMethod>),
+
+    NotFound,
+}
+
+struct Endpoint {
+    pattern: String,
+    handler: RequestHandler,
+    /// names of the route's params and catch-all, in the order they appear in the pattern
+    param_names: Vec<String>,
+    name: Option<String>,
+}
+
+/// Literal segments are keyed in `children`, a param segment goes to the
+/// `params` child with the same constraint and a trailing `*catch_all`
+/// segment to the `catch_all` child.
+#[derive(Default)]
 struct TrieNode {
-    params: RwLock<HashMap<String, i32>>,
     children: RwLock<HashMap<String, Arc<TrieNode>>>,
-    handler: RwLock<HashMap<String, RequestHandler>>,
+    /// constrained params first, so they get a chance before a bare `:param`
+    params: RwLock<Vec<ParamChild>>,
+    catch_all: RwLock<Option<Arc<TrieNode>>>,
+    handler: RwLock<HashMap<HTTPMethod, Endpoint>>,
 }
 
-#[derive(Clone, Debug)]
+impl TrieNode {
+    fn has_handlers(&self) -> bool {
+        !self.handler.read().unwrap().is_empty()
+    }
+
+    fn descendants(&self) -> Vec<Arc<TrieNode>> {
+        let mut nodes = self.children.read().unwrap().values().cloned().collect::<Vec<Arc<TrieNode>>>();
+
+        nodes.extend(self.params.read().unwrap().iter().map(|param| Arc::clone(&param.node)));
+
+        nodes.extend(self.catch_all.read().unwrap().clone());
+
+        nodes
+    }
+
+    /// Whether a request with `method` has a handler here, HEAD using GET's.
+    fn handles(&self, method: &HTTPMethod) -> bool {
+        let handler_map = self.handler.read().unwrap();
+
+        handler_map.contains_key(method) || (*method == HTTPMethod::HEAD && handler_map.contains_key(&HTTPMethod::GET))
+    }
+
+    /// Matches `segments` below this node, preferring literal segments over
+    /// params over a catch-all and backtracking when a branch leads nowhere
+    /// or ends on a node that `accepts` rejects.
+    fn find(self: &Arc<Self>, segments: &[&str], accepts: &dyn Fn(&TrieNode) -> bool, param_values: &mut Vec<String>) -> Option<Arc<TrieNode>> {
+        let Some((segment, rest)) = segments.split_first() else {
+            return if accepts(self) { Some(Arc::clone(self)) } else { None };
+        };
+
+        let key = if segment.is_empty() { "/" } else { segment };
+
+        let child = self.children.read().unwrap().get(key).cloned();
+
+        if let Some(found) = child.and_then(|child| child.find(rest, accepts, param_values)) {
+            return Some(found);
+        }
+
+        // params never match an empty segment
+        let params = if segment.is_empty() { Vec::new() } else { self.params.read().unwrap().clone() };
+
+        for param in params {
+            if !param.constraint.as_ref().map_or(true, |constraint| constraint.matches(segment)) {
+                continue;
+            }
+
+            param_values.push(segment.to_string());
+
+            if let Some(found) = param.node.find(rest, accepts, param_values) {
+                return Some(found);
+            }
+
+            param_values.pop();
+        }
+
+        let catch_all = self.catch_all.read().unwrap().clone();
+
+        if let Some(catch_all) = catch_all.filter(|catch_all| accepts(catch_all)) {
+            param_values.push(segments.join("/"));
+
+            return Some(catch_all);
+        }
+
+        None
+    }
+}
+
+fn with_middleware(middleware: Arc<[Middleware]>, handler: RequestHandler) -> RequestHandler {
+    if middleware.is_empty() {
+        return handler;
+    }
+
+    Arc::new(move |req, res| {
+        let middleware = Arc::clone(&middleware);
+        let handler = Arc::clone(&handler);
+
+        Box::pin(async move { Next::new(&middleware, &handler).run(req, res).await })
+    })
+}
+
+/// Returns the param child for `constraint`. Two different constraints at
+/// the same position are rejected, since whether they overlap is unknown.
+fn param_child_or_insert(node: &TrieNode, constraint: Option<Constraint>, pattern: &str) -> Result<Arc<TrieNode>, RouteError> {
+    let mut params = node.params.write().unwrap();
+
+    let source = constraint.as_ref().map(|constraint| constraint.source().to_string());
+
+    let existing = params.iter().find(|param| param.constraint.as_ref().map(|constraint| constraint.source().to_string()) == source);
+
+    if let Some(param) = existing {
+        return Ok(Arc::clone(&param.node));
+    }
+
+    let other = params.iter().find_map(|param| param.constraint.as_ref());
+
+    if let (Some(source), Some(other)) = (source, other) {
+        return Err(RouteError::OverlappingConstraints { constraint: source, existing: other.source().to_string(), pattern: pattern.to_string() });
+    }
+
+    let node = Arc::new(TrieNode::default());
+
+    params.push(ParamChild { constraint, node: Arc::clone(&node) });
+
+    params.sort_by_key(|param| param.constraint.is_none());
+
+    Ok(node)
+}
+
+fn push_param_name(param_names: &mut Vec<String>, name: &str, pattern: &str) -> Result<(), RouteError> {
+    if param_names.iter().any(|existing| existing == name) {
+        return Err(RouteError::DuplicateParam { name: name.to_string(), pattern: pattern.to_string() });
+    }
+
+    param_names.push(name.to_string());
+
+    Ok(())
+}
+
+fn child_or_insert(slot: &RwLock<Option<Arc<TrieNode>>>) -> Arc<TrieNode> {
+    let mut slot = slot.write().unwrap();
+
+    Arc::clone(slot.get_or_insert_with(|| Arc::new(TrieNode::default())))
+}
+
+/// The method of a route and the node it ends on.
+type LastRoute = (HTTPMethod, Arc<TrieNode>);
+
+#[derive(Clone)]
 pub struct Router {
     root: Arc<TrieNode>,
     params_regex: Arc<Regex>,
+    typed_params_regex: Arc<Regex>,
+    catch_all_regex: Arc<Regex>,
+    middleware: Arc<RwLock<Vec<Middleware>>>,
+    errors: Arc<RwLock<Vec<RouteError>>>,
+    /// route names to patterns, for `url_for`
+    names: Arc<RwLock<HashMap<String, String>>>,
+    /// the route registered last, the one `name` applies to
+    last_route: Arc<RwLock<Option<LastRoute>>>,
+}
+
+impl Debug for Router {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router").field("routes", &self.routes().collect::<Vec<RouteInfo>>()).finish()
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Router {
     pub fn new() -> Self {
         Self {
             root: Arc::new(TrieNode::default()),
-            params_regex: Arc::new(Regex::new(r":([a-z0-9_]+)").unwrap()),
+            params_regex: Arc::new(Regex::new(r"^:([a-z0-9_]+)(?:\((.+)\))?$").unwrap()),
+            typed_params_regex: Arc::new(Regex::new(r"^\{([a-z0-9_]+)(?::([a-z0-9_]+))?\}$").unwrap()),
+            catch_all_regex: Arc::new(Regex::new(r"^\*([a-z0-9_]+)$").unwrap()),
+            middleware: Arc::new(RwLock::new(Vec::new())),
+            errors: Arc::new(RwLock::new(Vec::new())),
+            names: Arc::new(RwLock::new(HashMap::new())),
+            last_route: Arc::new(RwLock::new(None)),
         }
     }
 
-    pub fn get(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::GET, path.to_string(), handler)
+    /// Adds a middleware around every route. Middleware run in the order they
+    /// were added, so the first one added is the outermost.
+    pub fn middleware<F>(&self, middleware: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response, Next<'a>) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.middleware.write().unwrap().push(Arc::new(middleware));
+
+        self
     }
 
-    pub fn post(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::POST, path.to_string(), handler)
+    pub fn middleware_chain(&self) -> Vec<Middleware> {
+        self.middleware.read().unwrap().clone()
     }
 
-    pub fn put(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::PUT, path.to_string(), handler)
+    /// Mounts every route of `router` under `prefix`. The middleware added to
+    /// `router` wrap its own routes only, inside the middleware of this router.
+    pub fn nest(&self, prefix: &str, router: Router) -> &Router {
+        let prefix = prefix.trim_end_matches('/');
+
+        let group_middleware: Arc<[Middleware]> = router.middleware_chain().into();
+
+        self.errors.write().unwrap().extend(router.errors.read().unwrap().iter().cloned());
+
+        let prefixed = |pattern: &str| match pattern {
+            "/" if !prefix.is_empty() => prefix.to_string(),
+            pattern => format!("{}{}", prefix, pattern),
+        };
+
+        let mut nodes = vec![Arc::clone(&router.root)];
+
+        while let Some(node) = nodes.pop() {
+            for (method, endpoint) in node.handler.read().unwrap().iter() {
+                let path = prefixed(&endpoint.pattern);
+
+                let handler = with_middleware(Arc::clone(&group_middleware), Arc::clone(&endpoint.handler));
+
+                self.define_route(method, path, handler);
+
+                // a route that could not be mounted already reported an error
+                let mounted = self.last_route.read().unwrap().is_some();
+
+                if let Some(name) = endpoint.name.as_deref().filter(|_| mounted) {
+                    self.name(name);
+                }
+            }
+
+            nodes.extend(node.descendants());
+        }
+
+        *self.last_route.write().unwrap() = None;
+
+        self
     }
 
-    pub fn patch(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::PATCH, path.to_string(), handler)
+    /// Names the route registered just before, so `url_for` can build URLs for
+    /// it. The name belongs to that method only, not to every route on the path.
+    pub fn name(&self, name: &str) -> &Router {
+        let Some((method, node)) = self.last_route.read().unwrap().clone() else {
+            self.errors.write().unwrap().push(RouteError::NothingToName(name.to_string()));
+
+            return self;
+        };
+
+        let mut handler_map = node.handler.write().unwrap();
+
+        let Some(endpoint) = handler_map.get_mut(&method) else { return self };
+
+        let mut names = self.names.write().unwrap();
+
+        match names.get(name) {
+            Some(existing) => {
+                self.errors.write().unwrap().push(RouteError::DuplicateName { name: name.to_string(), pattern: existing.clone() });
+            }
+
+            None => {
+                names.insert(name.to_string(), endpoint.pattern.clone());
+
+                endpoint.name = Some(name.to_string());
+            }
+        }
+
+        self
     }
 
-    pub fn delete(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::DELETE, path.to_string(), handler)
+    /// Builds the path of the route called `name`, percent-encoding the
+    /// `params` into its segments and appending `query` as a query string.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)], query: &[(&str, &str)]) -> Result<String, UrlError> {
+        let pattern = self.names.read().unwrap().get(name).cloned().ok_or_else(|| UrlError::UnknownRoute(name.to_string()))?;
+
+        let param = |param_name: &str, constraint: Option<Constraint>| {
+            let value = params.iter().find(|(key, _)| *key == param_name).map(|(_, value)| *value);
+
+            let Some(value) = value else {
+                return Err(UrlError::MissingParam { route: name.to_string(), param: param_name.to_string() });
+            };
+
+            // dot segments would be removed when the URL is requested
+            let is_dot_segment = |segment: &str| segment == "." || segment == "..";
+
+            let is_valid = !value.is_empty()
+                && !value.split('/').any(is_dot_segment)
+                && constraint.map_or(true, |constraint| constraint.matches(value));
+
+            if !is_valid {
+                return Err(UrlError::InvalidParam { route: name.to_string(), param: param_name.to_string(), value: value.to_string() });
+            }
+
+            Ok(value)
+        };
+
+        let mut segments = Vec::new();
+
+        for segment in pattern.split('/') {
+            if let Some(capture) = self.params_regex.captures(segment) {
+                let constraint = capture.get(2).and_then(|pattern| Constraint::pattern(pattern.as_str()).ok());
+
+                segments.push(encode_path_segment(param(&capture[1], constraint)?));
+            } else if let Some(capture) = self.typed_params_regex.captures(segment) {
+                let constraint = capture.get(2).and_then(|type_name| Constraint::of_type(type_name.as_str()));
+
+                segments.push(encode_path_segment(param(&capture[1], constraint)?));
+            } else if let Some(capture) = self.catch_all_regex.captures(segment) {
+                let value = param(&capture[1], None)?;
+
+                segments.push(value.split('/').map(encode_path_segment).collect::<Vec<String>>().join("/"));
+            } else {
+                segments.push(segment.to_string());
+            }
+        }
+
+        let mut url = segments.join("/");
+
+        if !query.is_empty() {
+            url.push('?');
+
+            url.push_str(&encode_query(query));
+        }
+
+        Ok(url)
     }
 
-    pub fn options(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::OPTIONS, path.to_string(), handler)
+    pub fn get<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::GET, path.to_string(), Arc::new(handler))
     }
 
-    pub fn head(&self, path: &str, handler: RequestHandler) -> &Router {
-        self.define_route(&HTTPMethod::HEAD, path.to_string(), handler)
+    pub fn post<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::POST, path.to_string(), Arc::new(handler))
     }
 
-    pub fn define_route(&self, method: &HTTPMethod, mut path: String, handler: RequestHandler) -> &Router {
-        let mut current_node = Arc::clone(&self.root);
+    pub fn put<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::PUT, path.to_string(), Arc::new(handler))
+    }
 
-        let mut path_params = HashMap::new();
+    pub fn patch<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::PATCH, path.to_string(), Arc::new(handler))
+    }
 
-        for capture in self.params_regex.captures_iter(&path) {
-            match capture.get(1) {
-                None => {}
-                Some(cap) => {
-                    path_params.insert(cap.as_str().to_string(), (cap.start() - 1) as i32);
-                }
-            }
+    pub fn delete<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::DELETE, path.to_string(), Arc::new(handler))
+    }
+
+    pub fn options<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::OPTIONS, path.to_string(), Arc::new(handler))
+    }
+
+    pub fn head<F>(&self, path: &str, handler: F) -> &Router
+    where
+        F: for<'a> Fn(&'a Request, &'a mut Response) -> BoxFuture<'a, HandlerResult> + Send + Sync + 'static,
+    {
+        self.define_route(&HTTPMethod::HEAD, path.to_string(), Arc::new(handler))
+    }
+
+    /// Registers `handler` for `method` on `path`. A route that is invalid or
+    /// clashes with an earlier one is not registered; the error is kept for
+    /// `validate` so the server refuses to start.
+    pub fn define_route(&self, method: &HTTPMethod, path: String, handler: RequestHandler) -> &Router {
+        let result = self.insert_route(method, path, handler);
+
+        *self.last_route.write().unwrap() = result.as_ref().ok().map(|node| (method.clone(), Arc::clone(node)));
+
+        if let Err(err) = result {
+            self.errors.write().unwrap().push(err);
         }
 
-        path = self.params_regex.replace_all(&path, "*").to_string();
+        self
+    }
 
-        for mut segment in path.split('/') {
-            if segment.is_empty() {
-                segment = "/";
+    /// Fails with the first route that could not be registered.
+    pub fn validate(&self) -> Result<(), RouteError> {
+        match self.errors.read().unwrap().first() {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Every registered route, sorted by pattern and method. HEAD and OPTIONS
+    /// answered automatically are not listed.
+    pub fn routes(&self) -> impl Iterator<Item = RouteInfo> {
+        let mut routes = Vec::new();
+
+        let mut nodes = vec![Arc::clone(&self.root)];
+
+        while let Some(node) = nodes.pop() {
+            for (method, endpoint) in node.handler.read().unwrap().iter() {
+                routes.push(RouteInfo {
+                    method: method.clone(),
+                    pattern: endpoint.pattern.clone(),
+                    param_names: endpoint.param_names.clone(),
+                    name: endpoint.name.clone(),
+                });
             }
 
-            let next_node = {
+            nodes.extend(node.descendants());
+        }
+
+        routes.sort_by(|a, b| a.pattern.cmp(&b.pattern).then_with(|| a.method.cmp(&b.method)));
+
+        routes.into_iter()
+    }
+
+    /// Returns the node the route ends on.
+    fn insert_route(&self, method: &HTTPMethod, path: String, handler: RequestHandler) -> Result<Arc<TrieNode>, RouteError> {
+        let mut current_node = Arc::clone(&self.root);
+
+        let mut param_names = Vec::new();
+
+        let segments = path.split('/').collect::<Vec<&str>>();
+
+        for (i, segment) in segments.iter().enumerate() {
+            let next_node = if let Some(capture) = self.params_regex.captures(segment) {
+                push_param_name(&mut param_names, &capture[1], &path)?;
+
+                let constraint = match capture.get(2) {
+                    None => None,
+
+                    Some(constraint) => match Constraint::pattern(constraint.as_str()) {
+                        Ok(constraint) => Some(constraint),
+
+                        Err(err) => {
+                            return Err(RouteError::InvalidPattern {
+                                constraint: constraint.as_str().to_string(),
+                                pattern: path,
+                                message: err.to_string(),
+                            });
+                        }
+                    },
+                };
+
+                param_child_or_insert(&current_node, constraint, &path)?
+            } else if let Some(capture) = self.typed_params_regex.captures(segment) {
+                push_param_name(&mut param_names, &capture[1], &path)?;
+
+                let constraint = match capture.get(2) {
+                    None => None,
+
+                    Some(type_name) => match Constraint::of_type(type_name.as_str()) {
+                        Some(constraint) => Some(constraint),
+
+                        None => {
+                            return Err(RouteError::UnknownType { type_name: type_name.as_str().to_string(), pattern: path });
+                        }
+                    },
+                };
+
+                param_child_or_insert(&current_node, constraint, &path)?
+            } else if let Some(capture) = self.catch_all_regex.captures(segment) {
+                if i != segments.len() - 1 {
+                    return Err(RouteError::CatchAllNotLast(path));
+                }
+
+                push_param_name(&mut param_names, &capture[1], &path)?;
+
+                child_or_insert(&current_node.catch_all)
+            } else if segment.starts_with([':', '{', '*']) {
+                return Err(RouteError::InvalidSegment { segment: segment.to_string(), pattern: path });
+            } else {
+                let key = if segment.is_empty() { "/" } else { segment };
+
                 let mut children = current_node.children.write().unwrap();
 
                 children
-                    .entry(segment.to_string())
+                    .entry(key.to_string())
                     .or_insert_with(|| Arc::new(TrieNode::default()))
                     .clone()
             };
@@ -92,56 +692,330 @@ impl Router {
 
         let mut handler_map = current_node.handler.write().unwrap();
 
-        handler_map.insert(method.to_string(), handler);
+        // routes that end on the same node match exactly the same paths, the
+        // first one registered keeps the handler
+        if let Some(existing) = handler_map.get(method) {
+            if existing.pattern == path {
+                return Err(RouteError::Duplicate { method: method.clone(), pattern: path });
+            }
 
-        current_node.params.write().unwrap().extend(path_params);
+            return Err(RouteError::Ambiguous { method: method.clone(), pattern: path, existing: existing.pattern.clone() });
+        }
 
-        self
+        handler_map.insert(method.clone(), Endpoint { pattern: path, handler, param_names, name: None });
+
+        drop(handler_map);
+
+        Ok(current_node)
     }
 
-    pub fn get_handler(&self, method: &HTTPMethod, path: &str) -> (Option<RequestHandler>, HashMap<String, i32>) {
-        let mut current_node = Arc::clone(&self.root);
+    pub fn get_handler(&self, method: &HTTPMethod, path: &str) -> RouteMatch {
+        if path == "*" {
+            return match method {
+                HTTPMethod::OPTIONS => RouteMatch::Options(self.all_methods()),
+                _ => RouteMatch::NotFound,
+            };
+        }
 
-        for mut segment in path.split('/') {
-            if segment.is_empty() {
-                segment = "/";
-            }
+        // decoded one by one so an escaped `/` stays inside its segment
+        // the server rejects such paths with 400 before routing
+        let Some(segments) = path.split('/').map(decode_path_segment).collect::<Option<Vec<String>>>() else {
+            return RouteMatch::NotFound;
+        };
 
-            let next_node = {
-                let children = current_node.children.read().unwrap();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<&str>>();
 
-                match children.get(segment) {
-                    Some(route) => {
-                        Some(Arc::clone(route))
-                    }
+        let mut param_values = Vec::new();
 
-                    None => {
-                        match children.get("*") {
-                            None => { None }
-                            Some(route) => {
-                                Some(Arc::clone(route))
-                            }
-                        }
-                    }
-                }
-            };
+        // a route for the method wins over one found earlier for other
+        // methods, which only decide between 405 and 404
+        let current_node = self
+            .root
+            .find(&segments, &|node| node.handles(method), &mut param_values)
+            .or_else(|| self.root.find(&segments, &|node| node.has_handlers(), &mut param_values));
 
+        let Some(current_node) = current_node else {
+            return RouteMatch::NotFound;
+        };
 
-            if let Some(r) = next_node {
-                current_node = r; // Use the cloned Arc here
-            } else {
-                let params = current_node.params.read().unwrap().clone();
+        let handler_map = current_node.handler.read().unwrap();
+
+        // HEAD runs the GET handler, the server drops the body when writing the response
+        let endpoint = match handler_map.get(method) {
+            None if *method == HTTPMethod::HEAD => handler_map.get(&HTTPMethod::GET),
+            endpoint => endpoint,
+        };
+
+        if let Some(endpoint) = endpoint {
+            let mut params = Params::default();
 
-                return (None, params);
+            for (name, value) in endpoint.param_names.iter().zip(param_values) {
+                params.insert(name.clone(), value);
             }
+
+            return RouteMatch::Found(Arc::clone(&endpoint.handler), params);
         }
 
-        let params = current_node.params.read().unwrap().clone();
+        let allowed_methods = Self::allowed_methods(handler_map.keys());
 
-        let handler_map = current_node.handler.read().unwrap();
+        match method {
+            HTTPMethod::OPTIONS => RouteMatch::Options(allowed_methods),
+            _ => RouteMatch::MethodNotAllowed(allowed_methods),
+        }
+    }
+
+    /// Every method some route answers to, as advertised for `OPTIONS *`.
+    pub fn all_methods(&self) -> Vec<HTTPMethod> {
+        let mut methods = Vec::new();
+
+        let mut nodes = vec![Arc::clone(&self.root)];
+
+        while let Some(node) = nodes.pop() {
+            methods.extend(node.handler.read().unwrap().keys().cloned());
+
+            nodes.extend(node.descendants());
+        }
+
+        Self::allowed_methods(methods.iter())
+    }
+
+    /// The registered methods plus the ones answered automatically: HEAD
+    /// wherever GET is registered and OPTIONS everywhere.
+    fn allowed_methods<'a>(registered: impl Iterator<Item = &'a HTTPMethod>) -> Vec<HTTPMethod> {
+        let mut methods = registered.cloned().collect::<Vec<HTTPMethod>>();
+
+        if methods.contains(&HTTPMethod::GET) {
+            methods.push(HTTPMethod::HEAD);
+        }
+
+        methods.push(HTTPMethod::OPTIONS);
+
+        methods.sort();
+
+        methods.dedup();
+
+        methods
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(patterns: &[&str]) -> Router {
+        let router = Router::new();
+
+        for pattern in patterns {
+            router.get(pattern, |_req, _res| Box::pin(async move { Ok(()) }));
+        }
+
+        router.validate().unwrap();
+
+        router
+    }
+
+    /// The params of the route `path` is routed to, `None` when nothing matches.
+    fn matched(router: &Router, path: &str) -> Option<Vec<(String, String)>> {
+        match router.get_handler(&HTTPMethod::GET, path) {
+            RouteMatch::Found(_, params) => Some(params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()),
+            _ => None,
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+
+    #[test]
+    fn literal_segments_win_over_params() {
+        let router = router(&["/users/:id", "/users/me"]);
+
+        assert_eq!(matched(&router, "/users/me"), params(&[]));
+
+        assert_eq!(matched(&router, "/users/42"), params(&[("id", "42")]));
+    }
+
+    #[test]
+    fn constrained_params_win_over_plain_ones() {
+        let router = router(&["/items/:slug", "/items/{id:u32}"]);
+
+        assert_eq!(matched(&router, "/items/7"), params(&[("id", "7")]));
+
+        assert_eq!(matched(&router, "/items/seven"), params(&[("slug", "seven")]));
+    }
+
+    #[test]
+    fn params_win_over_a_catch_all() {
+        let router = router(&["/static/*path", "/static/:file"]);
+
+        assert_eq!(matched(&router, "/static/app.js"), params(&[("file", "app.js")]));
+
+        assert_eq!(matched(&router, "/static/js/app.js"), params(&[("path", "js/app.js")]));
+    }
+
+    #[test]
+    fn backtracks_from_a_literal_to_a_param() {
+        let router = router(&["/a/b/c", "/a/:x/d"]);
+
+        assert_eq!(matched(&router, "/a/b/c"), params(&[]));
+
+        assert_eq!(matched(&router, "/a/b/d"), params(&[("x", "b")]));
+    }
+
+    #[test]
+    fn backtracks_from_a_param_to_a_catch_all() {
+        let router = router(&["/docs/:page/edit", "/docs/*rest"]);
+
+        assert_eq!(matched(&router, "/docs/intro/edit"), params(&[("page", "intro")]));
+
+        assert_eq!(matched(&router, "/docs/intro/view"), params(&[("rest", "intro/view")]));
+    }
+
+    #[test]
+    fn backtracks_past_routes_without_the_method() {
+        let router = Router::new();
+
+        router.get("/users/me", |_req, _res| Box::pin(async move { Ok(()) }));
+
+        router.delete("/users/:id", |_req, _res| Box::pin(async move { Ok(()) }));
+
+        router.validate().unwrap();
+
+        let deleted = match router.get_handler(&HTTPMethod::DELETE, "/users/me") {
+            RouteMatch::Found(_, params) => params.get("id").map(str::to_string),
+            _ => None,
+        };
+
+        assert_eq!(deleted.as_deref(), Some("me"));
+
+        assert!(matches!(router.get_handler(&HTTPMethod::HEAD, "/users/me"), RouteMatch::Found(_, params) if params.is_empty()));
+
+        let allowed = match router.get_handler(&HTTPMethod::PUT, "/users/me") {
+            RouteMatch::MethodNotAllowed(allowed) => allowed,
+            _ => Vec::new(),
+        };
+
+        assert_eq!(allowed, [HTTPMethod::GET, HTTPMethod::OPTIONS, HTTPMethod::HEAD]);
+    }
 
-        let handler = handler_map.get(&method.to_string()).cloned();
+    #[test]
+    fn rejects_malformed_param_segments() {
+        for pattern in ["/a/:userId", "/a/:", "/a/{id:U64}", "/a/{id", "/a/*Rest", "/a/*"] {
+            let router = Router::new();
 
-        (handler, params)
+            router.get(pattern, |_req, _res| Box::pin(async move { Ok(()) }));
+
+            assert!(matches!(router.validate(), Err(RouteError::InvalidSegment { .. })), "{}", pattern);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_different_constraints_at_the_same_position() {
+        let overlapping = Router::new();
+
+        overlapping.get(r"/n/:id(\d+)", |_req, _res| Box::pin(async move { Ok(()) }));
+
+        overlapping.delete("/n/{id:u64}", |_req, _res| Box::pin(async move { Ok(()) }));
+
+        assert!(matches!(overlapping.validate(), Err(RouteError::OverlappingConstraints { .. })));
+
+        let router = router(&["/n/{id:u64}", "/n/{id:u64}/edit", "/n/:name"]);
+
+        assert_eq!(matched(&router, "/n/7/edit"), params(&[("id", "7")]));
+    }
+
+    #[test]
+    fn params_do_not_match_empty_segments() {
+        let router = router(&["/users/:id"]);
+
+        assert_eq!(matched(&router, "/users/"), None);
+
+        assert_eq!(matched(&router, "/users"), None);
+    }
+
+    fn named_router() -> Router {
+        let router = Router::new();
+
+        router.get("/users/:id", |_req, _res| Box::pin(async move { Ok(()) })).name("user");
+
+        router.get("/items/{id:u32}", |_req, _res| Box::pin(async move { Ok(()) })).name("item");
+
+        router.get("/static/*path", |_req, _res| Box::pin(async move { Ok(()) })).name("static");
+
+        router.validate().unwrap();
+
+        router
+    }
+
+    #[test]
+    fn url_for_fills_in_params_and_query() {
+        let router = named_router();
+
+        assert_eq!(router.url_for("user", &[("id", "42")], &[]).unwrap(), "/users/42");
+
+        assert_eq!(router.url_for("item", &[("id", "7")], &[("page", "2"), ("q", "a b")]).unwrap(), "/items/7?page=2&q=a+b");
+
+        assert_eq!(router.url_for("static", &[("path", "css/app.css")], &[]).unwrap(), "/static/css/app.css");
+    }
+
+    #[test]
+    fn url_for_percent_encodes_params() {
+        let router = named_router();
+
+        assert_eq!(router.url_for("user", &[("id", "a/b c")], &[]).unwrap(), "/users/a%2Fb%20c");
+
+        assert_eq!(router.url_for("static", &[("path", "my dir/a?.txt")], &[]).unwrap(), "/static/my%20dir/a%3F.txt");
+    }
+
+    #[test]
+    fn url_for_rejects_unknown_routes_and_missing_params() {
+        let router = named_router();
+
+        assert!(matches!(router.url_for("nope", &[], &[]), Err(UrlError::UnknownRoute(_))));
+
+        assert!(matches!(router.url_for("user", &[], &[]), Err(UrlError::MissingParam { .. })));
+    }
+
+    #[test]
+    fn url_for_rejects_values_the_route_would_not_match() {
+        let router = named_router();
+
+        assert!(matches!(router.url_for("item", &[("id", "seven")], &[]), Err(UrlError::InvalidParam { .. })));
+
+        assert!(matches!(router.url_for("user", &[("id", "")], &[]), Err(UrlError::InvalidParam { .. })));
+
+        assert!(matches!(router.url_for("user", &[("id", "..")], &[]), Err(UrlError::InvalidParam { .. })));
+
+        assert!(matches!(router.url_for("static", &[("path", "css/../secret")], &[]), Err(UrlError::InvalidParam { .. })));
+    }
+
+    #[test]
+    fn url_for_builds_urls_the_router_matches() {
+        let router = named_router();
+
+        let url = router.url_for("user", &[("id", "a/b c")], &[]).unwrap();
+
+        assert_eq!(matched(&router, &url), params(&[("id", "a/b c")]));
+    }
+
+    #[test]
+    fn names_belong_to_a_single_method() {
+        let files = Router::new();
+
+        files.get("/:filename", |_req, _res| Box::pin(async move { Ok(()) })).name("file");
+
+        files.post("/:filename", |_req, _res| Box::pin(async move { Ok(()) }));
+
+        let router = Router::new();
+
+        router.nest("/files", files);
+
+        router.validate().unwrap();
+
+        let names = router.routes().map(|route| (route.method, route.name)).collect::<Vec<(HTTPMethod, Option<String>)>>();
+
+        assert_eq!(names, [(HTTPMethod::GET, Some("file".to_string())), (HTTPMethod::POST, None)]);
+
+        assert_eq!(router.url_for("file", &[("filename", "a.txt")], &[]).unwrap(), "/files/a.txt");
+    }
+}
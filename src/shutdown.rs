@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Asks a running `HttpServer` to stop accepting connections and drain the
+/// ones it already has.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    pub fn new(sender: Arc<watch::Sender<bool>>) -> Self {
+        Self { sender }
+    }
+
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+/// Resolves once the process receives SIGINT (Ctrl+C) or, on unix, SIGTERM.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            eprintln!("Could not listen for Ctrl+C: {}", err);
+
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+
+            Err(err) => {
+                eprintln!("Could not listen for SIGTERM: {}", err);
+
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
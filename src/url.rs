@@ -0,0 +1,211 @@
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything but the unreserved characters of RFC 3986.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Query string parameters in the order they appear. A key can appear more
+/// than once, and a key without `=` has an empty value.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    values: Vec<(String, String)>,
+}
+
+impl Query {
+    /// Decodes an `application/x-www-form-urlencoded` query string, so `+` is a space.
+    pub fn parse(query_string: &str) -> Self {
+        Self {
+            values: form_urlencoded::parse(query_string.as_bytes()).into_owned().collect(),
+        }
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.values.iter().filter(move |(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.values.iter().any(|(key, _)| key == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Percent-encodes `value` so it stays a single path segment.
+pub fn encode_path_segment(value: &str) -> String {
+    utf8_percent_encode(value, PATH_SEGMENT).to_string()
+}
+
+pub fn encode_query(query: &[(&str, &str)]) -> String {
+    form_urlencoded::Serializer::new(String::new()).extend_pairs(query).finish()
+}
+
+/// Decodes a single path segment. Invalid escapes are kept as they are, and
+/// a segment that does not decode to UTF-8 gives `None` rather than being
+/// changed to a replacement character.
+pub fn decode_path_segment(segment: &str) -> Option<String> {
+    percent_decode_str(segment).decode_utf8().ok().map(|segment| segment.into_owned())
+}
+
+/// Normalizes an origin-form path (RFC 3986, section 6.2.2): escaped
+/// unreserved characters are decoded and the other escapes upper-cased, then
+/// empty segments are collapsed and `.` and `..` segments removed. The result
+/// is still percent-encoded, so an escaped `/` does not start a new segment.
+pub fn normalize_path(path: &str) -> String {
+    let path = normalize_percent_encoding(path);
+
+    let raw_segments = path.split('/').skip(1).collect::<Vec<&str>>();
+
+    let mut segments = Vec::new();
+
+    for segment in &raw_segments {
+        match *segment {
+            "" | "." => {}
+
+            ".." => {
+                segments.pop();
+            }
+
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+
+    // "/a/", "/a/." and "/a/b/.." all keep the trailing slash
+    let trailing_slash = matches!(raw_segments.last(), Some(&"" | &"." | &".."));
+
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    normalized
+}
+
+fn normalize_percent_encoding(path: &str) -> String {
+    let bytes = path.as_bytes();
+
+    let mut normalized = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+
+    while i < bytes.len() {
+        // from_str_radix alone would accept a sign, as in "%+1"
+        let escape = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit) => {
+                std::str::from_utf8(hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            }
+
+            _ => None,
+        };
+
+        match escape {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(byte);
+
+                i += 3;
+            }
+
+            Some(byte) => {
+                normalized.extend(format!("%{:02X}", byte).bytes());
+
+                i += 3;
+            }
+
+            None => {
+                normalized.push(bytes[i]);
+
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&normalized).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_dot_segments() {
+        assert_eq!(normalize_path("/a/./b"), "/a/b");
+
+        assert_eq!(normalize_path("/a/b/../c"), "/a/c");
+
+        assert_eq!(normalize_path("/../../a"), "/a");
+
+        assert_eq!(normalize_path("/a/%2E%2E/b"), "/b");
+    }
+
+    #[test]
+    fn collapses_empty_segments() {
+        assert_eq!(normalize_path("//a///b"), "/a/b");
+
+        assert_eq!(normalize_path("/"), "/");
+
+        assert_eq!(normalize_path("//"), "/");
+    }
+
+    #[test]
+    fn keeps_the_trailing_slash() {
+        assert_eq!(normalize_path("/a/"), "/a/");
+
+        assert_eq!(normalize_path("/a/b/."), "/a/b/");
+
+        assert_eq!(normalize_path("/a/b/.."), "/a/");
+
+        assert_eq!(normalize_path("/a/.."), "/");
+    }
+
+    #[test]
+    fn decodes_unreserved_escapes_only() {
+        assert_eq!(normalize_path("/%7Euser/%61%62c"), "/~user/abc");
+
+        assert_eq!(normalize_path("/a%20b/%c3%a9"), "/a%20b/%C3%A9");
+    }
+
+    #[test]
+    fn keeps_escaped_slashes_inside_their_segment() {
+        assert_eq!(normalize_path("/a%2fb/c"), "/a%2Fb/c");
+
+        assert_eq!(normalize_path("/a/..%2F..%2Fetc"), "/a/..%2F..%2Fetc");
+    }
+
+    #[test]
+    fn decodes_segments_to_utf8_only() {
+        assert_eq!(decode_path_segment("caf%C3%A9%20au%2Flait").as_deref(), Some("café au/lait"));
+
+        assert_eq!(decode_path_segment("%zz").as_deref(), Some("%zz"));
+
+        assert_eq!(decode_path_segment("%C3%28"), None);
+
+        assert_eq!(decode_path_segment("%FF"), None);
+    }
+
+    #[test]
+    fn keeps_invalid_escapes_as_they_are() {
+        assert_eq!(normalize_path("/%+1"), "/%+1");
+
+        assert_eq!(normalize_path("/%-1"), "/%-1");
+
+        assert_eq!(normalize_path("/%zz"), "/%zz");
+
+        assert_eq!(normalize_path("/a%"), "/a%");
+
+        assert_eq!(normalize_path("/a%4"), "/a%4");
+    }
+}